@@ -0,0 +1,91 @@
+// Send/receive queue throughput: the old per-byte VecDeque<u8> pattern
+// against RingBuf slice copies, moving MSS-sized chunks through a 64 KiB
+// queue the way send_app / conn_try_send / recv do.
+//
+//   cargo bench --bench ring_throughput
+
+use std::collections::VecDeque;
+use std::hint::black_box;
+use std::time::Instant;
+use tcp_stack::util::ring::RingBuf;
+
+const TOTAL: usize = 256 * 1024 * 1024;
+const CAP: usize = 64 * 1024;
+const MSS: usize = 1460;
+
+fn vecdeque() -> f64 {
+    let src = vec![0xa5u8; MSS];
+    let mut q: VecDeque<u8> = VecDeque::with_capacity(CAP);
+    let mut seg = vec![0u8; MSS];
+    let start = Instant::now();
+    let mut moved = 0;
+    while moved < TOTAL {
+        while q.len() + MSS <= CAP {
+            for b in &src {
+                q.push_back(*b);
+            }
+        }
+        while q.len() >= MSS {
+            for slot in seg.iter_mut() {
+                *slot = q.pop_front().unwrap();
+            }
+            black_box(&seg);
+            moved += MSS;
+        }
+    }
+    mb_per_s(moved, start)
+}
+
+fn ringbuf() -> f64 {
+    let src = vec![0xa5u8; MSS];
+    let mut q = RingBuf::new(CAP);
+    let mut seg = vec![0u8; MSS];
+    let start = Instant::now();
+    let mut moved = 0;
+    while moved < TOTAL {
+        while q.free() >= MSS {
+            q.write(&src);
+        }
+        while q.len() >= MSS {
+            q.read(&mut seg);
+            black_box(&seg);
+            moved += MSS;
+        }
+    }
+    mb_per_s(moved, start)
+}
+
+// Send side with retransmission: peek each segment at its offset, and only
+// consume once "acked" (every other round).
+fn ringbuf_peek() -> f64 {
+    let src = vec![0xa5u8; MSS];
+    let mut q = RingBuf::new(CAP);
+    let mut seg = vec![0u8; MSS];
+    let start = Instant::now();
+    let mut moved = 0;
+    while moved < TOTAL {
+        while q.free() >= MSS {
+            q.write(&src);
+        }
+        let mut off = 0;
+        while off + MSS <= q.len() {
+            q.peek_at(off, &mut seg);
+            black_box(&seg);
+            off += MSS;
+            moved += MSS;
+        }
+        q.consume(off);
+    }
+    mb_per_s(moved, start)
+}
+
+fn mb_per_s(bytes: usize, start: Instant) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    println!("queue throughput, {} MiB in {MSS}-byte chunks", TOTAL / (1024 * 1024));
+    println!("  VecDeque<u8> per byte : {:>8.0} MiB/s", vecdeque());
+    println!("  RingBuf write/read    : {:>8.0} MiB/s", ringbuf());
+    println!("  RingBuf peek_at       : {:>8.0} MiB/s", ringbuf_peek());
+}
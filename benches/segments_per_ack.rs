@@ -0,0 +1,119 @@
+// Bulk transfer between two in-memory stacks, counting how many data
+// segments the sender emits per ACK it receives. With the window-filling
+// send loop this follows cwnd growth instead of staying at one.
+//
+//   cargo bench --bench segments_per_ack
+
+use std::time::Instant;
+use tcp_stack::tcp::{
+    parse_tcp, serialize_tcp, ConnHandle, ConnMeta, FourTuple, ListenAddr, ListenerConfig, TcpStack,
+    WireSeg,
+};
+
+const CLIENT_IP: [u8; 4] = [10, 0, 0, 2];
+const SERVER_IP: [u8; 4] = [10, 0, 0, 1];
+const PORT: u16 = 8080;
+const TOTAL: usize = 32 * 1024 * 1024;
+
+type Out = Vec<(ConnMeta, WireSeg<'static>)>;
+
+// Runs every segment through the real codec into `to`; returns what `to` sent back.
+fn deliver(to: &mut TcpStack, segs: Out, now: u128) -> Out {
+    let mut back = Vec::new();
+    for (meta, seg) in segs {
+        let bytes = serialize_tcp(&seg, meta.ip_src, meta.ip_dst);
+        let rx = parse_tcp(meta.ip_src, meta.ip_dst, &bytes).expect("parse");
+        let key = FourTuple {
+            src_ip: meta.ip_src,
+            dst_ip: meta.ip_dst,
+            src_port: rx.src_port,
+            dst_port: rx.dst_port,
+        };
+        back.extend(to.on_segment(now, key, &rx));
+    }
+    back
+}
+
+struct Stats {
+    acks: u64,
+    data_segs: u64,
+    max_burst: usize,
+}
+
+fn run(nodelay: bool, pacing: bool) -> (Stats, f64) {
+    let mut server = TcpStack::new();
+    let laddr = ListenAddr::any(PORT);
+    server.listen_on(laddr, ListenerConfig::default());
+    let mut client = TcpStack::new();
+
+    // 1 ms per hop keeps RTT samples and delayed ACKs meaningful
+    let mut now: u128 = 1;
+    let (h, syn) = client.connect(CLIENT_IP, SERVER_IP, PORT, now).expect("connect");
+    let synack = deliver(&mut server, syn, now);
+    now += 1;
+    let ack = deliver(&mut client, synack, now);
+    now += 1;
+    deliver(&mut server, ack, now);
+    let sh: ConnHandle = server.accept(laddr).expect("accept");
+    // keep the sender window-limited rather than application-limited
+    client.conns.get_mut(&h.0).unwrap().tcb.sendq.resize(1024 * 1024);
+    client.set_nodelay(h, nodelay);
+    client.set_pacing(h, pacing);
+
+    let chunk = vec![0x5au8; 16 * 1024];
+    let mut sink = vec![0u8; 64 * 1024];
+    let mut queued = 0;
+    let mut received = 0;
+    let mut to_server: Out = Vec::new();
+    let mut stats = Stats { acks: 0, data_segs: 0, max_burst: 0 };
+
+    let start = Instant::now();
+    while received < TOTAL {
+        while queued < TOTAL {
+            let (n, segs) = client.send_app(h, &chunk[..chunk.len().min(TOTAL - queued)], now);
+            to_server.extend(segs);
+            if n == 0 {
+                break;
+            }
+            queued += n;
+        }
+        now += 1;
+        let mut acks = deliver(&mut server, std::mem::take(&mut to_server), now);
+        loop {
+            let n = server.recv(sh, &mut sink);
+            if n == 0 {
+                break;
+            }
+            received += n;
+        }
+        acks.extend(server.on_timer(now));
+        now += 1;
+        for ack in acks {
+            let segs = deliver(&mut client, vec![ack], now);
+            let data = segs.iter().filter(|(_, s)| !s.payload.is_empty()).count();
+            stats.acks += 1;
+            stats.data_segs += data as u64;
+            stats.max_burst = stats.max_burst.max(data);
+            to_server.extend(segs);
+        }
+        to_server.extend(client.on_timer(now));
+    }
+    (stats, start.elapsed().as_secs_f64())
+}
+
+fn report(name: &str, (s, secs): (Stats, f64)) {
+    println!(
+        "  {name:<14} {:>6.2} segs/ACK  max burst {:>3}  {:>6} ACKs  {:>7.0} MiB/s",
+        s.data_segs as f64 / s.acks.max(1) as f64,
+        s.max_burst,
+        s.acks,
+        TOTAL as f64 / (1024.0 * 1024.0) / secs,
+    );
+}
+
+fn main() {
+    println!("bulk transfer, {} MiB", TOTAL / (1024 * 1024));
+    report("default", run(false, false));
+    report("nodelay", run(true, false));
+    report("paced", run(false, true));
+}
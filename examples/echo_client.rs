@@ -0,0 +1,29 @@
+// Echo client on the blocking socket API: connects from our stack, sends a
+// line and prints what comes back.
+//
+//   cargo run --example echo_client -- tcp.conf.example tap1 [SERVER_ADDR]
+//
+// SERVER_ADDR defaults to 10.0.0.1:8080.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use tcp_stack::api::socket::{Stack, TcpStream};
+use tcp_stack::config::Config;
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(path), Some(ifname)) = (args.next(), args.next()) else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "usage: echo_client CONFIG IFACE [SERVER_ADDR]"));
+    };
+    let addr = args.next().unwrap_or_else(|| "10.0.0.1:8080".to_string());
+
+    let cfg = Config::load(path)?;
+    let _stack = Stack::spawn(cfg.open(cfg.interface(Some(&ifname))?)?);
+
+    let mut s = TcpStream::connect(addr)?;
+    s.write_all(b"hello-from-tcp-stack")?;
+    let mut buf = [0u8; 1024];
+    let n = s.read(&mut buf)?;
+    println!("got {} bytes: {}", n, String::from_utf8_lossy(&buf[..n]));
+    Ok(())
+}
@@ -0,0 +1,36 @@
+// Echo server on the blocking socket API. Apart from Stack::spawn() this is
+// plain std::net code with the imports swapped.
+//
+//   cargo run --example echo_server -- [CONFIG [IFACE]]
+//
+// Without a config file the stack is tap0 as 10.0.0.1/24.
+
+use std::io;
+use std::thread;
+
+use tcp_stack::api::socket::{Stack, TcpListener};
+use tcp_stack::config::Config;
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let cfg = match args.next() {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let iface = cfg.interface(args.next().as_deref())?;
+    let _stack = Stack::spawn(cfg.open(iface)?);
+
+    let listener = TcpListener::bind("0.0.0.0:8080")?;
+    println!("echo server on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let mut s = stream?;
+        thread::spawn(move || {
+            let peer = s.peer_addr();
+            match s.try_clone().and_then(|mut r| io::copy(&mut r, &mut s)) {
+                Ok(n) => println!("{peer:?}: echoed {n} bytes"),
+                Err(e) => println!("{peer:?}: {e}"),
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,451 @@
+//! Async sockets on a single-threaded executor. Futures for accept, read,
+//! write and connect check the stack and, if they cannot finish yet, leave
+//! their Waker in the TcpStack connection (or listener) table; the stack
+//! wakes them when a segment, timer or close touches that entry.
+//!
+//! [`Runtime::block_on`] runs ready tasks, polls the NetStack and otherwise
+//! sleeps until the next TCP timer is due or the device turns readable. If
+//! the device fails, every socket future pending then or later fails with
+//! its error.
+//!
+//! ```no_run
+//! use std::io;
+//! use tcp_stack::api::aio::{Handle, Runtime, TcpListener};
+//! use tcp_stack::{Ipv4Config, ListenerConfig, Mac, NetStack, Tap};
+//!
+//! async fn echo(h: Handle) -> io::Result<()> {
+//!     let listener = TcpListener::bind(&h, "0.0.0.0:8080", ListenerConfig::default())?;
+//!     loop {
+//!         let (s, _) = listener.accept().await?;
+//!         h.spawn(async move {
+//!             let mut buf = [0; 4096];
+//!             while let Ok(n @ 1..) = s.read(&mut buf).await {
+//!                 if s.write_all(&buf[..n]).await.is_err() {
+//!                     break;
+//!                 }
+//!             }
+//!         });
+//!     }
+//! }
+//!
+//! let ip = Ipv4Config { addr: [10, 0, 0, 1], prefix_len: 24, gateway: None };
+//! let rt = Runtime::new(NetStack::new(Tap::open("tap0")?, Mac([2, 0, 0, 0, 0, 1]), ip));
+//! rt.block_on(echo(rt.handle()))?;
+//! # Ok::<(), io::Error>(())
+//! ```
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::{pin, Pin};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use super::socket::{sock, v4_addr};
+use crate::netdev::Device;
+use crate::stack::NetStack;
+use crate::tcp::{ConnHandle, ListenAddr, ListenerConfig, State, TcpError};
+use crate::util::time::now_millis;
+
+type Net = NetStack<Box<dyn Device + Send>>;
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+const MAIN: usize = usize::MAX; // the block_on() future's id in the ready queue
+
+// Ids of tasks to poll; wakers may fire from any thread.
+type ReadyQueue = Arc<Mutex<VecDeque<usize>>>;
+
+struct TaskWaker {
+    id: usize,
+    ready: ReadyQueue,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready.lock().unwrap().push_back(self.id);
+    }
+}
+
+struct Shared {
+    net: RefCell<Net>,
+    tasks: RefCell<Vec<Option<Task>>>,
+    ready: ReadyQueue,
+    failed: RefCell<Option<(ErrorKind, String)>>, // the device error, once it fails
+}
+
+// Spawns tasks and opens sockets; cloned into every task that needs one.
+#[derive(Clone)]
+pub struct Handle {
+    shared: Rc<Shared>,
+}
+
+impl Handle {
+    pub fn spawn(&self, fut: impl Future<Output = ()> + 'static) {
+        let mut tasks = self.shared.tasks.borrow_mut();
+        let id = match tasks.iter().position(|t| t.is_none()) {
+            Some(i) => i,
+            None => {
+                tasks.push(None);
+                tasks.len() - 1
+            }
+        };
+        tasks[id] = Some(Box::pin(fut));
+        self.shared.ready.lock().unwrap().push_back(id);
+    }
+
+    // Runs `f` on the stack, for anything the socket API does not cover.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Net) -> R) -> R {
+        f(&mut self.shared.net.borrow_mut())
+    }
+
+    // Err once the device has failed; socket futures check it first.
+    fn failed(&self) -> io::Result<()> {
+        match &*self.shared.failed.borrow() {
+            Some((kind, msg)) => Err(io::Error::new(*kind, msg.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // Records a device error and wakes every task to see it.
+    fn fail(&self, e: io::Error) {
+        *self.shared.failed.borrow_mut() = Some((e.kind(), e.to_string()));
+        let tasks = self.shared.tasks.borrow();
+        let ids = tasks.iter().enumerate().filter(|(_, t)| t.is_some()).map(|(id, _)| id);
+        self.shared.ready.lock().unwrap().extend(ids.chain([MAIN]));
+    }
+
+    fn waker(&self, id: usize) -> Waker {
+        Waker::from(Arc::new(TaskWaker { id, ready: self.shared.ready.clone() }))
+    }
+
+    // The task is taken out of its slot while it runs, so it can spawn more.
+    fn run(&self, id: usize) {
+        let Some(mut task) = self.shared.tasks.borrow_mut().get_mut(id).and_then(Option::take) else {
+            return; // finished, or woken twice
+        };
+        let waker = self.waker(id);
+        if task.as_mut().poll(&mut Context::from_waker(&waker)).is_pending() {
+            self.shared.tasks.borrow_mut()[id] = Some(task);
+        }
+    }
+}
+
+pub struct Runtime {
+    handle: Handle,
+}
+
+impl Runtime {
+    pub fn new<D: Device + Send + 'static>(net: NetStack<D>) -> Runtime {
+        let shared = Shared {
+            net: RefCell::new(net.boxed()),
+            tasks: RefCell::new(Vec::new()),
+            ready: ReadyQueue::default(),
+            failed: RefCell::new(None),
+        };
+        Runtime { handle: Handle { shared: Rc::new(shared) } }
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    // Drives `fut` and every spawned task until `fut` completes. Spawned
+    // tasks still pending then stay parked for the next block_on().
+    pub fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        let waker = self.handle.waker(MAIN);
+        let ready = self.handle.shared.ready.clone();
+        ready.lock().unwrap().push_back(MAIN);
+        loop {
+            // only what is ready now, so a task that wakes itself cannot
+            // keep the stack from being polled
+            let batch: Vec<usize> = ready.lock().unwrap().drain(..).collect();
+            for id in batch {
+                if id != MAIN {
+                    self.handle.run(id);
+                } else if let Poll::Ready(v) = fut.as_mut().poll(&mut Context::from_waker(&waker)) {
+                    return v;
+                }
+            }
+            let mut net = self.handle.shared.net.borrow_mut();
+            let mut res = net.poll(now_millis()).map(drop);
+            if res.is_ok() && ready.lock().unwrap().is_empty() {
+                res = net.wait(now_millis());
+            }
+            drop(net);
+            if let Err(e) = res {
+                self.handle.fail(e);
+            }
+        }
+    }
+}
+
+impl Drop for Runtime {
+    // Parked tasks hold Handles, and so the Shared that holds them.
+    fn drop(&mut self) {
+        let tasks = std::mem::take(&mut *self.handle.shared.tasks.borrow_mut());
+        drop(tasks); // their sockets borrow the stack as they go
+    }
+}
+
+pub struct TcpListener {
+    handle: Handle,
+    addr: ListenAddr,
+}
+
+impl TcpListener {
+    pub fn bind(handle: &Handle, addr: impl ToSocketAddrs, cfg: ListenerConfig) -> io::Result<TcpListener> {
+        let a = v4_addr(addr)?;
+        let addr = if a.ip().is_unspecified() { ListenAddr::any(a.port()) } else { ListenAddr::new(a.ip().octets(), a.port()) };
+        handle.with(|net| -> io::Result<()> {
+            if addr.ip.is_some_and(|ip| ip != net.ip.addr) {
+                return Err(ErrorKind::AddrNotAvailable.into());
+            }
+            if !net.listen(addr, cfg) {
+                return Err(ErrorKind::AddrInUse.into());
+            }
+            Ok(())
+        })?;
+        Ok(TcpListener { handle: handle.clone(), addr })
+    }
+
+    pub fn accept(&self) -> Accept<'_> {
+        Accept { listener: self }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(sock(self.addr.ip.unwrap_or([0; 4]), self.addr.port))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        self.handle.with(|net| {
+            let out = net.tcp.unlisten(self.addr, now_millis());
+            let _ = net.transmit(out, now_millis());
+        });
+    }
+}
+
+pub struct Accept<'a> {
+    listener: &'a TcpListener,
+}
+
+impl Future for Accept<'_> {
+    type Output = io::Result<(TcpStream, SocketAddr)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let l = self.listener;
+        l.handle.failed()?;
+        let h = l.handle.with(|net| {
+            let h = net.accept(l.addr);
+            if h.is_none() {
+                net.tcp.register_accept_waker(l.addr, cx.waker());
+            }
+            h
+        });
+        match h {
+            Some(h) => Poll::Ready(Ok((TcpStream::new(&l.handle, h), sock(h.0.src_ip, h.0.src_port)))),
+            None => Poll::Pending,
+        }
+    }
+}
+
+pub struct TcpStream {
+    handle: Handle,
+    h: ConnHandle,
+}
+
+impl TcpStream {
+    fn new(handle: &Handle, h: ConnHandle) -> Self {
+        Self { handle: handle.clone(), h }
+    }
+
+    pub fn connect(handle: &Handle, addr: impl ToSocketAddrs) -> io::Result<Connect> {
+        let a = v4_addr(addr)?;
+        let h = handle.with(|net| net.connect(a.ip().octets(), a.port(), now_millis()))?;
+        Ok(Connect { stream: Some(TcpStream::new(handle, h)) })
+    }
+
+    // Ok(0) at end of stream.
+    pub fn read<'a>(&'a self, buf: &'a mut [u8]) -> Read<'a> {
+        Read { stream: self, buf }
+    }
+
+    // Queues what fits in the send buffer, waiting until something does.
+    pub fn write<'a>(&'a self, data: &'a [u8]) -> Write<'a> {
+        Write { stream: self, data }
+    }
+
+    pub async fn write_all(&self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let n = self.write(data).await?;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    // FIN after the queued data; reads keep working until the peer's FIN.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.handle.with(|net| net.shutdown(self.h, now_millis()))
+    }
+
+    // Closes as set_linger() says and resolves once the peer has acked
+    // everything, or with TimedOut if the linger time ran out first.
+    // Dropping the stream instead closes without waiting.
+    pub fn close(self) -> Close {
+        let r = self.handle.with(|net| net.close(self.h, now_millis()));
+        Close { stream: self, closed: r }
+    }
+
+    // Resets the connection, discarding anything not yet sent or read.
+    pub fn abort(self) -> io::Result<()> {
+        self.handle.with(|net| net.abort(self.h, now_millis()))
+    }
+
+    // SO_LINGER; see TcpStack::set_linger().
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        let ms = linger.map(|d| d.as_micros().div_ceil(1000) as u64);
+        self.handle.with(|net| net.tcp.set_linger(self.h, ms));
+        Ok(())
+    }
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        let ms = self.handle.with(|net| net.tcp.conns.get(&self.h.0).and_then(|c| c.tcb.linger));
+        Ok(ms.map(Duration::from_millis))
+    }
+
+    // The error that ended the connection, once.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        Ok(self.handle.with(|net| net.tcp.take_error(self.h)).map(Into::into))
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(sock(self.h.0.src_ip, self.h.0.src_port))
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(sock(self.h.0.dst_ip, self.h.0.dst_port))
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        self.handle.with(|net| {
+            let _ = net.close(self.h, now_millis());
+            net.tcp.release(self.h);
+        });
+    }
+}
+
+pub struct Connect {
+    stream: Option<TcpStream>,
+}
+
+impl Future for Connect {
+    type Output = io::Result<TcpStream>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let s = self.stream.as_ref().expect("Connect polled after completion");
+        s.handle.failed()?;
+        let state = s.handle.with(|net| match net.state(s.h) {
+            Some(State::SynSent) => {
+                net.tcp.register_send_waker(s.h, cx.waker());
+                Ok(State::SynSent)
+            }
+            Some(state) => Ok(state),
+            None => Err(net.tcp.take_error(s.h).unwrap_or(TcpError::TimedOut)),
+        });
+        match state {
+            Ok(State::SynSent) => Poll::Pending,
+            Ok(_) => Poll::Ready(Ok(self.stream.take().unwrap())),
+            Err(e) => Poll::Ready(Err(e.into())),
+        }
+    }
+}
+
+pub struct Close {
+    stream: TcpStream,
+    closed: io::Result<()>,
+}
+
+impl Future for Close {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.closed.is_err() {
+            return Poll::Ready(std::mem::replace(&mut self.closed, Ok(())));
+        }
+        let s = &self.stream;
+        s.handle.failed()?;
+        s.handle.with(|net| match net.tcp.conns.get(&s.h.0) {
+            Some(c) if !c.tcb.fin_acked() => {
+                net.tcp.register_send_waker(s.h, cx.waker());
+                Poll::Pending
+            }
+            _ => Poll::Ready(net.tcp.take_error(s.h).map_or(Ok(()), |e| Err(e.into()))),
+        })
+    }
+}
+
+pub struct Read<'a> {
+    stream: &'a TcpStream,
+    buf: &'a mut [u8],
+}
+
+impl Future for Read<'_> {
+    type Output = io::Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let h = this.stream.h;
+        this.stream.handle.failed()?;
+        this.stream.handle.with(|net| {
+            let n = net.recv(h, this.buf);
+            match net.state(h) {
+                _ if n > 0 || this.buf.is_empty() => Poll::Ready(Ok(n)),
+                // nothing more will come once the peer's FIN is in
+                Some(State::SynRcvd | State::Established | State::FinWait1 | State::FinWait2) => {
+                    net.tcp.register_recv_waker(h, cx.waker());
+                    Poll::Pending
+                }
+                _ => Poll::Ready(net.tcp.take_error(h).map_or(Ok(0), |e| Err(e.into()))),
+            }
+        })
+    }
+}
+
+pub struct Write<'a> {
+    stream: &'a TcpStream,
+    data: &'a [u8],
+}
+
+impl Future for Write<'_> {
+    type Output = io::Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let h = self.stream.h;
+        let data = self.data;
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        self.stream.handle.failed()?;
+        self.stream.handle.with(|net| {
+            if !net.tcp.may_send(h) {
+                return Poll::Ready(Err(net.tcp.take_error(h).map_or(ErrorKind::BrokenPipe.into(), Into::into)));
+            }
+            match net.send(h, data, now_millis()) {
+                Ok(0) => {
+                    net.tcp.register_send_waker(h, cx.waker()); // send buffer full
+                    Poll::Pending
+                }
+                r => Poll::Ready(r),
+            }
+        })
+    }
+}
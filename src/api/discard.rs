@@ -0,0 +1,22 @@
+use crate::api::server::{Action, Handler};
+use crate::netdev::Device;
+use crate::stack::{SocketHandle, TcpSocket};
+
+use std::io::ErrorKind;
+
+// RFC 863: reads everything and answers nothing.
+pub struct Discard;
+
+impl<D: Device> Handler<D> for Discard {
+    fn on_ready(&mut self, _conn: SocketHandle, s: &mut TcpSocket<'_, D>, _now_ms: u128) -> Action {
+        let mut buf = [0u8; 4096];
+        loop {
+            match s.recv_slice(&mut buf) {
+                Ok(0) => return Action::Close,
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Action::Keep,
+                Err(_) => return Action::Close,
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// A minimal HTTP/1.0 endpoint: one request per connection, answered by a
+// callback and closed after the response. Enough for a status page.
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+
+use crate::api::server::{Action, Handler};
+use crate::netdev::Device;
+use crate::stack::{SocketHandle, TcpSocket};
+use crate::tcp::TcpError;
+
+const MAX_REQUEST: usize = 8192; // request line and headers
+
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self { status, content_type: "text/plain; charset=utf-8", body: body.into().into_bytes() }
+    }
+    pub fn html(body: impl Into<String>) -> Self {
+        Self { status: 200, content_type: "text/html; charset=utf-8", body: body.into().into_bytes() }
+    }
+    pub fn not_found() -> Self {
+        Self::text(404, "not found\n")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let head = format!(
+            "HTTP/1.0 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len()
+        );
+        [head.as_bytes(), &self.body].concat()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+#[derive(Default)]
+struct Exchange {
+    req: Vec<u8>,
+    resp: Option<Vec<u8>>,
+    sent: usize,
+}
+
+pub struct Http<F> {
+    route: F,
+    conns: HashMap<SocketHandle, Exchange>,
+}
+
+impl<F: FnMut(&Request<'_>) -> Response> Http<F> {
+    pub fn new(route: F) -> Self {
+        Self { route, conns: HashMap::new() }
+    }
+
+    // Some(response) once the request head is complete or cannot be.
+    fn respond(&mut self, req: &[u8]) -> Option<Response> {
+        let Some(end) = req.windows(4).position(|w| w == b"\r\n\r\n") else {
+            return (req.len() >= MAX_REQUEST).then(|| Response::text(431, "request too large\n"));
+        };
+        let head = std::str::from_utf8(&req[..end]).unwrap_or("");
+        let mut words = head.lines().next().unwrap_or("").split(' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some(method), Some(path), Some(v)) if v.starts_with("HTTP/1.") => {
+                Some((self.route)(&Request { method, path }))
+            }
+            _ => Some(Response::text(400, "bad request\n")),
+        }
+    }
+}
+
+impl<D: Device, F: FnMut(&Request<'_>) -> Response> Handler<D> for Http<F> {
+    fn on_ready(&mut self, conn: SocketHandle, s: &mut TcpSocket<'_, D>, now_ms: u128) -> Action {
+        let mut x = self.conns.remove(&conn).unwrap_or_default();
+        while x.resp.is_none() {
+            let mut buf = [0u8; 1024];
+            let room = (MAX_REQUEST - x.req.len()).min(buf.len());
+            match s.recv_slice(&mut buf[..room]) {
+                Ok(0) => return Action::Close, // gone before asking
+                Ok(n) => {
+                    x.req.extend_from_slice(&buf[..n]);
+                    x.resp = self.respond(&x.req).map(|r| r.serialize());
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.conns.insert(conn, x);
+                    return Action::Keep;
+                }
+                Err(_) => return Action::Close,
+            }
+        }
+        let resp = x.resp.as_ref().unwrap();
+        match s.send_slice(&resp[x.sent..], now_ms) {
+            Ok(n) => x.sent += n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => return Action::Close,
+        }
+        if x.sent == resp.len() {
+            return Action::Close; // our FIN follows the response
+        }
+        self.conns.insert(conn, x);
+        Action::Keep
+    }
+
+    fn on_close(&mut self, conn: SocketHandle, _error: Option<TcpError>) {
+        self.conns.remove(&conn);
+    }
+}
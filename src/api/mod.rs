@@ -1 +1,6 @@
+pub mod aio;
+pub mod discard;
 pub mod echo;
+pub mod http;
+pub mod server;
+pub mod socket;
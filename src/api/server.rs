@@ -0,0 +1,182 @@
+//! Many listening ports on one NetStack, each bound to its own application.
+//! A [`Handler`] (a type like [`Echo`](super::echo::Echo) or a closure) is
+//! called with the connection's socket right after it is accepted and then
+//! whenever its readiness has changed since the handler last ran. Data it
+//! leaves unread does not bring it back on its own: a handler that stops
+//! reading because it cannot send runs again once it can. It returns
+//! [`Action::Close`] once it is done; the server then closes the connection
+//! and tells the handler through `on_close`, with the error if it failed.
+//!
+//! ```
+//! use tcp_stack::api::discard::Discard;
+//! use tcp_stack::api::echo::Echo;
+//! use tcp_stack::api::server::{Action, Server};
+//! use tcp_stack::{pipe, Ipv4Config, Mac, NetStack};
+//!
+//! let (a, b) = pipe();
+//! let ip = |last| Ipv4Config { addr: [10, 0, 0, last], prefix_len: 24, gateway: None };
+//! let mut net = NetStack::new(a, Mac([2, 0, 0, 0, 0, 1]), ip(1));
+//! let mut client = NetStack::new(b, Mac([2, 0, 0, 0, 0, 2]), ip(2));
+//!
+//! let mut server = Server::new();
+//! server.serve(&mut net, 7, Echo::new())?;
+//! server.serve(&mut net, 9, Discard)?;
+//! // a callback works too: greet, then hang up
+//! server.serve(&mut net, 13, |_, sock: &mut tcp_stack::TcpSocket<'_, _>, now| {
+//!     let _ = sock.send_slice(b"hi\n", now);
+//!     Action::Close
+//! })?;
+//!
+//! let echo = client.connect_socket([10, 0, 0, 1], 7, 0)?;
+//! let hi = client.connect_socket([10, 0, 0, 1], 13, 0)?;
+//! client.socket(echo).send_slice(b"ping", 0)?;
+//! let (mut got, mut greeting) = (Vec::new(), Vec::new());
+//! for now in (0..3_000).step_by(10) {
+//!     server.poll(&mut net, now)?;
+//!     client.poll(now)?;
+//!     let mut buf = [0; 64];
+//!     if let Ok(n) = client.socket(echo).recv_slice(&mut buf) {
+//!         got.extend_from_slice(&buf[..n]);
+//!     }
+//!     if let Ok(n) = client.socket(hi).recv_slice(&mut buf) {
+//!         greeting.extend_from_slice(&buf[..n]);
+//!     }
+//! }
+//! assert_eq!(got, b"ping");
+//! assert_eq!(greeting, b"hi\n");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::io::Result;
+
+use crate::netdev::Device;
+use crate::stack::{NetStack, Readiness, SocketHandle, TcpSocket};
+use crate::tcp::{ListenAddr, ListenerConfig, TcpError};
+use crate::util::time::now_millis;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Keep,
+    Close, // FIN after whatever is queued; the handle is freed
+}
+
+pub trait Handler<D: Device> {
+    // `conn` tells connections of the same listener apart, for handlers
+    // that keep per-connection state.
+    fn on_ready(&mut self, conn: SocketHandle, sock: &mut TcpSocket<'_, D>, now_ms: u128) -> Action;
+    // The server let go of `conn`: the handler closed it, it failed (with
+    // `error`), or it finished closing on its own.
+    fn on_close(&mut self, _conn: SocketHandle, _error: Option<TcpError>) {}
+}
+
+impl<D, F> Handler<D> for F
+where
+    D: Device,
+    F: FnMut(SocketHandle, &mut TcpSocket<'_, D>, u128) -> Action,
+{
+    fn on_ready(&mut self, conn: SocketHandle, sock: &mut TcpSocket<'_, D>, now_ms: u128) -> Action {
+        self(conn, sock, now_ms)
+    }
+}
+
+struct Conn {
+    app: usize,
+    seen: Option<Readiness>, // after the handler's last turn; None until its first
+}
+
+pub struct Server<D: Device> {
+    apps: Vec<Box<dyn Handler<D>>>,
+    listeners: HashMap<SocketHandle, usize>, // -> index into apps
+    conns: HashMap<SocketHandle, Conn>,
+}
+
+impl<D: Device> Default for Server<D> {
+    fn default() -> Self {
+        Self { apps: Vec::new(), listeners: HashMap::new(), conns: HashMap::new() }
+    }
+}
+
+impl<D: Device> Server<D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Listens on `port` of every address, with the stack's TCP settings.
+    pub fn serve(&mut self, net: &mut NetStack<D>, port: u16, app: impl Handler<D> + 'static) -> Result<SocketHandle> {
+        self.serve_on(net, ListenAddr::any(port), ListenerConfig::default(), Box::new(app))
+    }
+
+    pub fn serve_on(
+        &mut self,
+        net: &mut NetStack<D>,
+        addr: ListenAddr,
+        cfg: ListenerConfig,
+        app: Box<dyn Handler<D>>,
+    ) -> Result<SocketHandle> {
+        let h = net.listen_socket(addr, cfg)?;
+        self.apps.push(app);
+        self.listeners.insert(h, self.apps.len() - 1);
+        Ok(h)
+    }
+
+    pub fn connections(&self) -> usize {
+        self.conns.len()
+    }
+
+    // Polls the stack, accepts on every listener and hands each connection
+    // with news to its application. News is judged against what the
+    // connection looked like after its handler last ran, not against the
+    // previous poll, so a handler that drains a buffer hears when it
+    // fills again, and one that could not act is left alone until
+    // something changes.
+    pub fn poll(&mut self, net: &mut NetStack<D>, now_ms: u128) -> Result<()> {
+        net.poll(now_ms)?;
+        for (&l, &app) in &self.listeners {
+            while let Ok(c) = net.socket(l).accept() {
+                self.conns.insert(c, Conn { app, seen: None });
+            }
+        }
+        let visit: Vec<SocketHandle> = self
+            .conns
+            .iter()
+            .filter(|&(&h, c)| c.seen != Some(net.sockets.readiness(&net.tcp, h)))
+            .map(|(&h, _)| h)
+            .collect();
+        for h in visit {
+            self.dispatch(net, h, now_ms)?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, net: &mut NetStack<D>, h: SocketHandle, now_ms: u128) -> Result<()> {
+        let Some(app) = self.conns.get(&h).map(|c| c.app) else {
+            return Ok(());
+        };
+        let mut sock = net.socket(h);
+        let error = sock.take_error();
+        let action = match error {
+            Some(_) => Action::Close,
+            None => self.apps[app].on_ready(h, &mut sock, now_ms),
+        };
+        if action == Action::Close || sock.state().is_none() {
+            self.conns.remove(&h);
+            self.apps[app].on_close(h, error);
+            return net.remove_socket(h, now_ms);
+        }
+        let seen = net.sockets.readiness(&net.tcp, h);
+        if let Some(c) = self.conns.get_mut(&h) {
+            c.seen = Some(seen);
+        }
+        Ok(())
+    }
+
+    // Serves until the device fails, sleeping until a frame arrives or the
+    // next TCP timer is due.
+    pub fn run(&mut self, net: &mut NetStack<D>) -> Result<()> {
+        loop {
+            net.wait(now_millis())?;
+            self.poll(net, now_millis())?;
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! Blocking sockets in the shape of std::net, so code written against
+//! std::net::{TcpListener, TcpStream} can switch stacks by changing an import.
+//!
+//! A stack thread ([`Stack::spawn`]) owns the NetStack and polls it; socket
+//! calls lock it, and block on a condition variable the thread signals after
+//! every poll. Between polls the thread sleeps in the device until a frame
+//! arrives, a timer is due or a socket call wakes it. The first stack spawned
+//! is the default that bind()/connect() use; dropping the returned
+//! [`StackThread`] stops it.
+//!
+//! ```
+//! use std::io::{Read, Write};
+//! use tcp_stack::api::socket::{Stack, TcpListener, TcpStream};
+//! use tcp_stack::{pipe, Ipv4Config, ListenerConfig, Mac, NetStack};
+//!
+//! let (a, b) = pipe();
+//! let ip = |last| Ipv4Config { addr: [10, 0, 0, last], prefix_len: 24, gateway: None };
+//! let server = Stack::spawn(NetStack::new(a, Mac([2, 0, 0, 0, 0, 1]), ip(1)));
+//! let client = Stack::spawn(NetStack::new(b, Mac([2, 0, 0, 0, 0, 2]), ip(2)));
+//!
+//! let listener = TcpListener::bind_on(&server, "0.0.0.0:7", ListenerConfig::default())?;
+//! std::thread::spawn(move || {
+//!     let (mut s, _) = listener.accept()?;
+//!     std::io::copy(&mut s.try_clone()?, &mut s)
+//! });
+//!
+//! let mut s = TcpStream::connect_on(&client, "10.0.0.1:7")?;
+//! s.write_all(b"ping")?;
+//! let mut buf = [0; 4];
+//! s.read_exact(&mut buf)?;
+//! assert_eq!(&buf, b"ping");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, ToSocketAddrs};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::netdev::{Device, Waiter};
+use crate::stack::NetStack;
+use crate::tcp::{ConnHandle, ListenAddr, ListenerConfig, State, TcpError};
+use crate::util::time::now_millis;
+
+// For devices that cannot wake the stack thread, it sleeps at most this
+// long, so timers armed by socket calls in the meantime are not late by more.
+const POLL_TICK_MS: u64 = 1;
+
+type Net = NetStack<Box<dyn Device + Send>>;
+
+struct Shared {
+    net: Mutex<Net>,
+    polled: Condvar, // signalled after every poll
+    waiter: Option<Arc<dyn Waiter>>,
+    stopped: AtomicBool,
+    error: Mutex<Option<io::Error>>, // the latest failed poll, for take_error()
+}
+
+#[derive(Clone)]
+pub struct Stack {
+    shared: Arc<Shared>,
+}
+
+// The running stack thread; dropping it stops the thread and waits for it.
+// Sockets still open on the stack then fail instead of blocking.
+pub struct StackThread {
+    stack: Stack,
+    thread: Option<JoinHandle<()>>,
+}
+
+static DEFAULT: Mutex<Option<Stack>> = Mutex::new(None);
+
+impl Stack {
+    // Starts the stack thread. The first stack spawned becomes the default
+    // until its thread is dropped.
+    pub fn spawn<D: Device + Send + 'static>(net: NetStack<D>) -> StackThread {
+        let shared = Arc::new(Shared {
+            waiter: net.dev.waiter(),
+            net: Mutex::new(net.boxed()),
+            polled: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            error: Mutex::new(None),
+        });
+        let s = Stack { shared: shared.clone() };
+        let thread = thread::spawn(move || {
+            while !shared.stopped.load(Ordering::Acquire) {
+                let delay = {
+                    let mut net = shared.net.lock().unwrap();
+                    let now = now_millis();
+                    if let Err(e) = net.poll(now) {
+                        *shared.error.lock().unwrap() = Some(e);
+                    }
+                    shared.polled.notify_all();
+                    net.poll_delay(now)
+                };
+                match &shared.waiter {
+                    Some(w) => {
+                        if let Err(e) = w.wait(delay) {
+                            *shared.error.lock().unwrap() = Some(e);
+                            thread::sleep(Duration::from_millis(POLL_TICK_MS));
+                        }
+                    }
+                    None => thread::sleep(Duration::from_millis(delay.map_or(POLL_TICK_MS, |d| d.min(POLL_TICK_MS)))),
+                }
+            }
+            // wake the blocked socket calls so they see the stop
+            let _net = shared.net.lock().unwrap();
+            shared.polled.notify_all();
+        });
+        DEFAULT.lock().unwrap().get_or_insert_with(|| s.clone());
+        StackThread { stack: s, thread: Some(thread) }
+    }
+
+    pub fn default_stack() -> io::Result<Stack> {
+        DEFAULT.lock().unwrap().clone().ok_or_else(|| io::Error::new(ErrorKind::NotConnected, "no stack spawned"))
+    }
+
+    // Runs `f` with the stack locked, for anything the socket API does not cover.
+    pub fn with<R>(&self, f: impl FnOnce(&mut Net) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    // The latest error from polling the device, if any, clearing it.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.shared.error.lock().unwrap().take()
+    }
+
+    // Unlocking wakes the stack thread, so whatever the caller queued goes
+    // out now rather than at the next timer.
+    fn lock(&self) -> Locked<'_> {
+        Locked { net: Some(self.shared.net.lock().unwrap()), stack: self }
+    }
+
+    fn wake(&self) {
+        if let Some(w) = &self.shared.waiter {
+            w.wake();
+        }
+    }
+
+    fn stopped(&self) -> io::Result<()> {
+        match self.shared.stopped.load(Ordering::Acquire) {
+            true => Err(io::Error::new(ErrorKind::NotConnected, "stack stopped")),
+            false => Ok(()),
+        }
+    }
+
+    // Blocks until `ready` returns Some, re-checking after every poll.
+    fn wait_for<R>(
+        &self,
+        deadline: Option<Instant>,
+        mut ready: impl FnMut(&mut Net) -> Option<io::Result<R>>,
+    ) -> io::Result<R> {
+        let mut net = self.lock();
+        loop {
+            if let Some(r) = ready(&mut net) {
+                return r;
+            }
+            self.stopped()?;
+            let guard = match deadline {
+                None => self.shared.polled.wait(net.take()).unwrap(),
+                Some(d) => {
+                    let left = d.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(ErrorKind::WouldBlock.into());
+                    }
+                    self.shared.polled.wait_timeout(net.take(), left).unwrap().0
+                }
+            };
+            net.net = Some(guard);
+        }
+    }
+}
+
+impl Deref for StackThread {
+    type Target = Stack;
+    fn deref(&self) -> &Stack {
+        &self.stack
+    }
+}
+
+impl Drop for StackThread {
+    fn drop(&mut self) {
+        self.stack.shared.stopped.store(true, Ordering::Release);
+        self.stack.wake();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+        let mut default = DEFAULT.lock().unwrap();
+        if default.as_ref().is_some_and(|d| Arc::ptr_eq(&d.shared, &self.stack.shared)) {
+            *default = None;
+        }
+    }
+}
+
+struct Locked<'a> {
+    net: Option<MutexGuard<'a, Net>>, // None only while wait_for() sleeps
+    stack: &'a Stack,
+}
+
+impl<'a> Locked<'a> {
+    fn take(&mut self) -> MutexGuard<'a, Net> {
+        self.net.take().unwrap()
+    }
+}
+
+impl Deref for Locked<'_> {
+    type Target = Net;
+    fn deref(&self) -> &Net {
+        self.net.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Locked<'_> {
+    fn deref_mut(&mut self) -> &mut Net {
+        self.net.as_mut().unwrap()
+    }
+}
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        self.net.take();
+        self.stack.wake();
+    }
+}
+
+pub(super) fn v4_addr(addr: impl ToSocketAddrs) -> io::Result<SocketAddrV4> {
+    addr.to_socket_addrs()?
+        .find_map(|a| match a {
+            SocketAddr::V4(a) => Some(a),
+            SocketAddr::V6(_) => None,
+        })
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no IPv4 address"))
+}
+
+pub(super) fn sock(ip: [u8; 4], port: u16) -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(ip), port))
+}
+
+pub struct TcpListener {
+    stack: Stack,
+    addr: ListenAddr,
+}
+
+impl TcpListener {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<TcpListener> {
+        Self::bind_on(&Stack::default_stack()?, addr, ListenerConfig::default())
+    }
+
+    pub fn bind_on(stack: &Stack, addr: impl ToSocketAddrs, cfg: ListenerConfig) -> io::Result<TcpListener> {
+        let a = v4_addr(addr)?;
+        let ip = a.ip().octets();
+        let addr = if a.ip().is_unspecified() { ListenAddr::any(a.port()) } else { ListenAddr::new(ip, a.port()) };
+        let mut net = stack.lock();
+        if addr.ip.is_some_and(|ip| ip != net.ip.addr) {
+            return Err(ErrorKind::AddrNotAvailable.into());
+        }
+        if !net.listen(addr, cfg) {
+            return Err(ErrorKind::AddrInUse.into());
+        }
+        Ok(TcpListener { stack: stack.clone(), addr })
+    }
+
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let h = self.stack.wait_for(None, |net| net.accept(self.addr).map(Ok))?;
+        let peer = sock(h.0.src_ip, h.0.src_port);
+        Ok((TcpStream::new(&self.stack, h), peer))
+    }
+
+    pub fn incoming(&self) -> impl Iterator<Item = io::Result<TcpStream>> + '_ {
+        std::iter::repeat_with(|| self.accept().map(|(s, _)| s))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        let ip = self.addr.ip.unwrap_or([0; 4]);
+        Ok(sock(ip, self.addr.port))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut net = self.stack.lock();
+        let out = net.tcp.unlisten(self.addr, now_millis());
+        let _ = net.transmit(out, now_millis());
+    }
+}
+
+// The connection itself, shared by try_clone()d streams. As with a socket,
+// options set through one handle apply to all of them.
+struct Conn {
+    stack: Stack,
+    h: ConnHandle,
+    opts: Mutex<Opts>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Opts {
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    read_shut: bool,
+}
+
+impl Drop for Conn {
+    // Like closing the last descriptor: the FIN goes out after queued data
+    // (or a RST, under a zero linger time). With a linger time this blocks
+    // until the FIN is acked, or the stack resets the connection when the
+    // time is up.
+    fn drop(&mut self) {
+        let h = self.h;
+        let mut net = self.stack.lock();
+        let _ = net.close(h, now_millis());
+        net.tcp.release(h);
+        let lingering = net.tcp.conns.get(&h.0).is_some_and(|c| c.tcb.linger.is_some());
+        drop(net);
+        if lingering {
+            let _ = self.stack.wait_for(None, |net| match net.tcp.conns.get(&h.0) {
+                Some(c) if !c.tcb.fin_acked() => None,
+                _ => Some(Ok(())),
+            });
+        }
+    }
+}
+
+pub struct TcpStream {
+    conn: Arc<Conn>,
+}
+
+impl TcpStream {
+    fn new(stack: &Stack, h: ConnHandle) -> Self {
+        Self { conn: Arc::new(Conn { stack: stack.clone(), h, opts: Mutex::default() }) }
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        Self::connect_on(&Stack::default_stack()?, addr)
+    }
+
+    pub fn connect_on(stack: &Stack, addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        let a = v4_addr(addr)?;
+        let h = stack.lock().connect(a.ip().octets(), a.port(), now_millis())?;
+        stack.wait_for(None, |net| match net.state(h) {
+            Some(State::SynSent) => None,
+            Some(_) => Some(Ok(())),
+            None => Some(Err(net.tcp.take_error(h).unwrap_or(TcpError::TimedOut).into())),
+        })?;
+        Ok(TcpStream::new(stack, h))
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(sock(self.conn.h.0.src_ip, self.conn.h.0.src_port))
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(sock(self.conn.h.0.dst_ip, self.conn.h.0.dst_port))
+    }
+
+    fn opts(&self) -> MutexGuard<'_, Opts> {
+        self.conn.opts.lock().unwrap()
+    }
+
+    // None blocks forever; a timed-out read or write fails with WouldBlock.
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        if dur == Some(Duration::ZERO) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        self.opts().read_timeout = dur;
+        Ok(())
+    }
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        if dur == Some(Duration::ZERO) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        self.opts().write_timeout = dur;
+        Ok(())
+    }
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.opts().read_timeout)
+    }
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.opts().write_timeout)
+    }
+
+    pub fn set_nodelay(&self, on: bool) -> io::Result<()> {
+        self.conn.stack.lock().tcp.set_nodelay(self.conn.h, on);
+        Ok(())
+    }
+    pub fn nodelay(&self) -> io::Result<bool> {
+        let net = self.conn.stack.lock();
+        Ok(net.tcp.conns.get(&self.conn.h.0).is_some_and(|c| c.tcb.nodelay))
+    }
+
+    // SO_LINGER, applied when the last handle is dropped: None closes in
+    // the background, Some(ZERO) resets the connection, Some(d) waits up to
+    // `d` for the peer to ack everything and resets it if it does not.
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        let ms = linger.map(|d| d.as_micros().div_ceil(1000) as u64);
+        self.conn.stack.lock().tcp.set_linger(self.conn.h, ms);
+        Ok(())
+    }
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        let net = self.conn.stack.lock();
+        let ms = net.tcp.conns.get(&self.conn.h.0).and_then(|c| c.tcb.linger);
+        Ok(ms.map(Duration::from_millis))
+    }
+
+    // Write: FIN after the queued data. Read: later reads return 0.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.opts().read_shut = true;
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.conn.stack.lock().shutdown(self.conn.h, now_millis())?;
+        }
+        Ok(())
+    }
+
+    // The error that ended the connection, like SO_ERROR; reads and writes
+    // report it too, once their data is used up.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        Ok(self.conn.stack.lock().tcp.take_error(self.conn.h).map(Into::into))
+    }
+
+    // Another handle to the same connection, which closes when the last
+    // handle is dropped.
+    pub fn try_clone(&self) -> io::Result<TcpStream> {
+        Ok(TcpStream { conn: self.conn.clone() })
+    }
+}
+
+impl Read for &TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let opts = *self.opts();
+        if opts.read_shut || buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = opts.read_timeout.map(|d| Instant::now() + d);
+        let h = self.conn.h;
+        self.conn.stack.wait_for(deadline, |net| {
+            let n = net.recv(h, buf);
+            match net.state(h) {
+                _ if n > 0 => Some(Ok(n)),
+                // nothing more will come once the peer's FIN is in
+                Some(State::SynRcvd | State::Established | State::FinWait1 | State::FinWait2) => None,
+                _ => Some(net.tcp.take_error(h).map_or(Ok(0), |e| Err(e.into()))),
+            }
+        })
+    }
+}
+
+impl Write for &TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.opts().write_timeout.map(|d| Instant::now() + d);
+        let h = self.conn.h;
+        self.conn.stack.wait_for(deadline, |net| {
+            if !matches!(net.state(h), Some(State::SynRcvd | State::Established | State::CloseWait)) {
+                return Some(Err(net.tcp.take_error(h).map_or(ErrorKind::BrokenPipe.into(), Into::into)));
+            }
+            match net.send(h, buf, now_millis()) {
+                Ok(0) if net.tcp.conns.get(&h.0).is_some_and(|c| c.tcb.fin_queued) => {
+                    Some(Err(ErrorKind::BrokenPipe.into()))
+                }
+                Ok(0) => None, // send buffer full
+                r => Some(r),
+            }
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
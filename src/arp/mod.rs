@@ -1,4 +1,6 @@
-use crate::l2::ethernet::{Mac, ETH_P_ARP};
+use crate::error::StackError;
+use crate::l2::ethernet::Mac;
+use std::fmt;
 use std::time::{Instant, Duration};
 
 const HTYPE_ETH: u16 = 1;
@@ -8,24 +10,42 @@ const PLEN_IPV4: u8 = 4;
 const OPCODE_REQUEST: u16 = 1;
 const OPCODE_REPLY:   u16 = 2;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Ipv4(pub [u8;4]);
 
 impl Ipv4 {
-    pub fn parse(s: &str) -> Self {
-        let mut a = [0u8;4];
-        for (i, part) in s.split('.').enumerate() {
-            a[i] = part.parse::<u8>().unwrap();
+    // Dotted quad, "10.0.0.1".
+    pub fn parse(s: &str) -> Result<Self, StackError> {
+        let a: std::net::Ipv4Addr = s.parse().map_err(|_| StackError::BadAddress(s.to_string()))?;
+        Ok(Ipv4(a.octets()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArpError {
+    Truncated,
+    Unsupported, // not Ethernet hardware / IPv4 protocol addresses
+    BadOpcode(u16),
+}
+
+impl fmt::Display for ArpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArpError::Truncated => f.write_str("ARP packet shorter than 28 bytes"),
+            ArpError::Unsupported => f.write_str("ARP for other than Ethernet/IPv4"),
+            ArpError::BadOpcode(op) => write!(f, "ARP opcode {op}"),
         }
-        Ipv4(a)
     }
 }
 
+impl std::error::Error for ArpError {}
+
 #[derive(Clone)]
 pub struct ArpEntry {
     pub ip: Ipv4,
     pub mac: Mac,
     pub updated: Instant,
+    pub permanent: bool, // configured; never expires or gets relearned
 }
 
 pub struct ArpCache {
@@ -43,15 +63,20 @@ impl ArpCache {
     }
     pub fn insert(&mut self, ip: Ipv4, mac: Mac) {
         if let Some(e) = self.entries.iter_mut().find(|e| e.ip.0 == ip.0) {
-            e.mac = mac; e.updated = Instant::now();
+            if !e.permanent { e.mac = mac; e.updated = Instant::now(); }
         } else {
-            self.entries.push(ArpEntry{ ip, mac, updated: Instant::now() });
+            self.entries.push(ArpEntry{ ip, mac, updated: Instant::now(), permanent: false });
         }
     }
+    // A static entry, like `arp -s`.
+    pub fn insert_permanent(&mut self, ip: Ipv4, mac: Mac) {
+        self.entries.retain(|e| e.ip.0 != ip.0);
+        self.entries.push(ArpEntry{ ip, mac, updated: Instant::now(), permanent: true });
+    }
 
     pub fn gc(&mut self) {
         let ttl = Duration::from_secs(60);
-        self.entries.retain(|e| e.updated.elapsed() < ttl);
+        self.entries.retain(|e| e.permanent || e.updated.elapsed() < ttl);
     }
 }
 
@@ -83,12 +108,22 @@ pub fn build_reply(our_mac: Mac, our_ip: Ipv4, dst_mac: Mac, dst_ip: Ipv4) -> Ve
     p
 }
 
-pub fn parse(packet: &[u8]) -> Option<(u16, Mac, Ipv4, Mac, Ipv4)> {
-    if packet.len() < 28 { return None; }
+pub fn parse(packet: &[u8]) -> Result<(u16, Mac, Ipv4, Mac, Ipv4), ArpError> {
+    if packet.len() < 28 {
+        return Err(ArpError::Truncated);
+    }
+    let htype = u16::from_be_bytes([packet[0], packet[1]]);
+    let ptype = u16::from_be_bytes([packet[2], packet[3]]);
+    if htype != HTYPE_ETH || ptype != PTYPE_IPV4 || packet[4] != HLEN_ETH || packet[5] != PLEN_IPV4 {
+        return Err(ArpError::Unsupported);
+    }
     let op = u16::from_be_bytes([packet[6], packet[7]]);
+    if op != OPCODE_REQUEST && op != OPCODE_REPLY {
+        return Err(ArpError::BadOpcode(op));
+    }
     let smac = Mac([packet[8],packet[9],packet[10],packet[11],packet[12],packet[13]]);
     let sip  = Ipv4([packet[14],packet[15],packet[16],packet[17]]);
     let tmac = Mac([packet[18],packet[19],packet[20],packet[21],packet[22],packet[23]]);
     let tip  = Ipv4([packet[24],packet[25],packet[26],packet[27]]);
-    Some((op, smac, sip, tmac, tip))
+    Ok((op, smac, sip, tmac, tip))
 }
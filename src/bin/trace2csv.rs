@@ -0,0 +1,74 @@
+// Turns one connection's JSON-lines trace (see src/tcp/trace.rs) into CSV
+// for plotting sequence numbers and cwnd over time:
+//
+//   cargo run --bin trace2csv -- trace/10.0.0.1_8080-10.0.0.2_40000.jsonl > conn.csv
+//
+// Each row is one event; seq/ack are relative to our first sent sequence
+// number, and cwnd/ssthresh/srtt carry the latest known value forward.
+
+use std::io::{self, BufRead, BufReader, Write};
+
+// Flat objects only, as the tracer writes them: {"k":1,"s":"text",...}
+fn fields(line: &str) -> Vec<(&str, &str)> {
+    let body = line.trim().trim_start_matches('{').trim_end_matches('}');
+    body.split(',')
+        .filter_map(|kv| kv.split_once(':'))
+        .map(|(k, v)| (k.trim().trim_matches('"'), v.trim().trim_matches('"')))
+        .collect()
+}
+
+fn main() -> io::Result<()> {
+    let input: Box<dyn BufRead> = match std::env::args().nth(1) {
+        Some(path) => Box::new(BufReader::new(std::fs::File::open(path)?)),
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut out = io::stdout().lock();
+    writeln!(out, "t_ms,event,seq,ack,len,cwnd,ssthresh,srtt_ms,rto_ms")?;
+
+    let mut base: Option<u32> = None;
+    let (mut cwnd, mut ssthresh, mut srtt, mut rto) = (String::new(), String::new(), String::new(), String::new());
+    for line in input.lines() {
+        let line = line?;
+        let f = fields(&line);
+        let get = |k: &str| f.iter().find(|(name, _)| *name == k).map(|&(_, v)| v);
+        let num = |k: &str| get(k).and_then(|v| v.parse::<u32>().ok());
+        let Some(ev) = get("ev") else { continue };
+        let t = get("t").unwrap_or("");
+        if ev == "seg_out" && base.is_none() {
+            base = num("seq");
+        }
+        let rel = |v: Option<u32>| match (v, base) {
+            (Some(v), Some(b)) => v.wrapping_sub(b).to_string(),
+            _ => String::new(),
+        };
+
+        let (mut seq, mut ack, mut len) = (String::new(), String::new(), String::new());
+        match ev {
+            "seg_out" => {
+                seq = rel(num("seq"));
+                len = get("len").unwrap_or("").to_string();
+            }
+            "seg_in" => {
+                if get("flags").is_some_and(|fl| fl.contains('A')) {
+                    ack = rel(num("ack"));
+                }
+                len = get("len").unwrap_or("").to_string();
+            }
+            "retransmit" => {
+                seq = rel(num("seq"));
+                len = get("len").unwrap_or("").to_string();
+            }
+            "cwnd" => {
+                cwnd = get("cwnd").unwrap_or("").to_string();
+                ssthresh = get("ssthresh").unwrap_or("").to_string();
+            }
+            "rtt" => {
+                srtt = get("srtt_ms").unwrap_or("").to_string();
+                rto = get("rto_ms").unwrap_or("").to_string();
+            }
+            _ => {}
+        }
+        writeln!(out, "{t},{ev},{seq},{ack},{len},{cwnd},{ssthresh},{srtt},{rto}")?;
+    }
+    Ok(())
+}
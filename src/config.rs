@@ -0,0 +1,282 @@
+//! The stack's config file: interfaces with their addresses, routes and
+//! static ARP entries, plus TCP tuning. One `key = value` per line, `#`
+//! starts a comment, and `[section]` headers say what the keys are about.
+//! `route` and `arp` may repeat. Besides the [`TcpConfig`] fields, `[tcp]`
+//! takes `ephemeral_ports = MIN-MAX` and `trace_dir`, a directory for
+//! JSON-lines connection traces.
+//!
+//! ```
+//! use tcp_stack::config::Config;
+//!
+//! let cfg = Config::parse("
+//!     [interface tap0]
+//!     mac     = 02:00:00:00:00:01
+//!     address = 10.0.0.1/24
+//!     gateway = 10.0.0.254
+//!     route   = 192.168.7.0/24 via 10.0.0.2
+//!     arp     = 10.0.0.2 02:00:00:00:00:02
+//!
+//!     [tcp]
+//!     mss             = 1200
+//!     timestamps      = off
+//!     ephemeral_ports = 50000-50999
+//! ")?;
+//! let tap0 = cfg.interface(None)?;
+//! assert_eq!(tap0.ip.gateway, Some([10, 0, 0, 254]));
+//! assert_eq!(tap0.routes[0].via, [10, 0, 0, 2]);
+//! assert_eq!(cfg.tcp.mss, 1200);
+//! assert_eq!(cfg.ephemeral_ports, (50000, 50999));
+//! # Ok::<(), tcp_stack::StackError>(())
+//! ```
+
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::arp;
+use crate::error::StackError;
+use crate::l2::ethernet::Mac;
+use crate::netdev::{Device, Tap};
+use crate::stack::{Ipv4Config, NetStack, Route};
+use crate::tcp::trace::JsonLinesTracer;
+use crate::tcp::ports::{EphemeralPorts, EPHEMERAL_MAX, EPHEMERAL_MIN};
+use crate::tcp::TcpConfig;
+
+// The largest IPv4 datagram less the IPv4 and TCP headers.
+const MAX_MSS: usize = 65535 - 40;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub name: String, // the TAP device
+    pub mac: Mac,
+    pub ip: Ipv4Config,
+    pub routes: Vec<Route>,
+    pub arp: Vec<(arp::Ipv4, Mac)>, // permanent entries
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub interfaces: Vec<Interface>,
+    pub tcp: TcpConfig,
+    pub ephemeral_ports: (u16, u16), // local ports for active opens, inclusive
+    pub trace_dir: Option<PathBuf>,  // a JSON-lines trace per connection
+}
+
+impl Default for Config {
+    // What the binary used before there was a config file: tap0 as 10.0.0.1/24.
+    fn default() -> Self {
+        Config {
+            interfaces: vec![Interface {
+                name: "tap0".to_string(),
+                mac: Mac([0x02, 0, 0, 0, 0, 0x01]),
+                ip: Ipv4Config { addr: [10, 0, 0, 1], prefix_len: 24, gateway: None },
+                routes: Vec::new(),
+                arp: Vec::new(),
+            }],
+            tcp: TcpConfig::default(),
+            ephemeral_ports: (EPHEMERAL_MIN, EPHEMERAL_MAX),
+            trace_dir: None,
+        }
+    }
+}
+
+enum Section {
+    None,
+    Interface,
+    Tcp,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, StackError> {
+        Config::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, StackError> {
+        let mut cfg = Config { interfaces: Vec::new(), ..Config::default() };
+        let mut section = Section::None;
+        let mut headers = Vec::new(); // line of each [interface]
+        let mut rto_line = 0; // the last rto_min_ms / rto_max_ms
+        for (i, raw) in text.lines().enumerate() {
+            let line = i + 1;
+            let err = |msg: String| StackError::Config { line, msg };
+            let text = raw.split('#').next().unwrap().trim();
+            if text.is_empty() {
+                continue;
+            }
+            if let Some(head) = text.strip_prefix('[') {
+                let head = head.strip_suffix(']').ok_or_else(|| err("unclosed section header".into()))?;
+                let mut words = head.split_whitespace();
+                section = match (words.next(), words.next(), words.next()) {
+                    (Some("interface"), Some(name), None) => {
+                        headers.push(line);
+                        cfg.interfaces.push(Interface {
+                            name: name.to_string(),
+                            mac: Mac([0; 6]),
+                            ip: Ipv4Config { addr: [0; 4], prefix_len: 32, gateway: None },
+                            routes: Vec::new(),
+                            arp: Vec::new(),
+                        });
+                        Section::Interface
+                    }
+                    (Some("tcp"), None, None) => Section::Tcp,
+                    _ => return Err(err(format!("unknown section [{head}]"))),
+                };
+                continue;
+            }
+            let (key, value) = text.split_once('=').ok_or_else(|| err("expected key = value".into()))?;
+            let (key, value) = (key.trim(), value.trim());
+            let at = |e: StackError| match e {
+                StackError::BadAddress(s) => err(format!("{key}: bad address {s:?}")),
+                e => e,
+            };
+            match section {
+                Section::None => return Err(err(format!("{key} outside of a section"))),
+                Section::Interface => {
+                    let iface = cfg.interfaces.last_mut().unwrap();
+                    match key {
+                        "mac" => iface.mac = Mac::parse(value).map_err(at)?,
+                        "address" => {
+                            let (addr, prefix_len) = parse_prefix(value).map_err(at)?;
+                            iface.ip.addr = addr;
+                            iface.ip.prefix_len = prefix_len;
+                        }
+                        "gateway" => iface.ip.gateway = Some(arp::Ipv4::parse(value).map_err(at)?.0),
+                        "route" => {
+                            let mut words = value.split_whitespace();
+                            let (Some(dest), Some("via"), Some(via), None) =
+                                (words.next(), words.next(), words.next(), words.next())
+                            else {
+                                return Err(err("route: expected ADDR/LEN via GATEWAY".into()));
+                            };
+                            let (dest, prefix_len) = parse_prefix(dest).map_err(at)?;
+                            let via = arp::Ipv4::parse(via).map_err(at)?.0;
+                            iface.routes.push(Route { dest, prefix_len, via });
+                        }
+                        "arp" => {
+                            let mut words = value.split_whitespace();
+                            let (Some(ip), Some(mac), None) = (words.next(), words.next(), words.next()) else {
+                                return Err(err("arp: expected ADDR MAC".into()));
+                            };
+                            iface.arp.push((arp::Ipv4::parse(ip).map_err(at)?, Mac::parse(mac).map_err(at)?));
+                        }
+                        _ => return Err(err(format!("unknown interface key {key}"))),
+                    }
+                }
+                Section::Tcp => match key {
+                    "ephemeral_ports" => cfg.ephemeral_ports = parse_port_range(value).map_err(err)?,
+                    "trace_dir" => cfg.trace_dir = Some(PathBuf::from(value)),
+                    "rto_min_ms" | "rto_max_ms" => {
+                        rto_line = line;
+                        set_tcp(&mut cfg.tcp, key, value).map_err(err)?
+                    }
+                    _ => set_tcp(&mut cfg.tcp, key, value).map_err(err)?,
+                },
+            }
+        }
+        if cfg.tcp.rto_min_ms > cfg.tcp.rto_max_ms {
+            let msg = format!("rto_min_ms {} is above rto_max_ms {}", cfg.tcp.rto_min_ms, cfg.tcp.rto_max_ms);
+            return Err(StackError::Config { line: rto_line, msg });
+        }
+        for (iface, &line) in cfg.interfaces.iter().zip(&headers) {
+            if iface.mac.0 == [0; 6] || iface.ip.addr == [0; 4] {
+                let msg = format!("interface {} needs a mac and an address", iface.name);
+                return Err(StackError::Config { line, msg });
+            }
+        }
+        Ok(cfg)
+    }
+
+    // The interface called `name`, or the first one.
+    pub fn interface(&self, name: Option<&str>) -> Result<&Interface, StackError> {
+        let found = match name {
+            Some(n) => self.interfaces.iter().find(|i| i.name == n),
+            None => self.interfaces.first(),
+        };
+        found.ok_or_else(|| StackError::Device {
+            name: name.unwrap_or("").to_string(),
+            source: io::Error::new(ErrorKind::NotFound, "no such interface in the config"),
+        })
+    }
+
+    // A NetStack for `iface` on `dev`, with its routes, ARP entries, the
+    // TCP tuning and the tracer applied. Fails if trace_dir cannot be created.
+    pub fn stack<D: Device>(&self, iface: &Interface, dev: D) -> Result<NetStack<D>, StackError> {
+        let mut net = NetStack::new(dev, iface.mac, iface.ip);
+        net.routes = iface.routes.clone();
+        for &(ip, mac) in &iface.arp {
+            net.arp.insert_permanent(ip, mac);
+        }
+        net.tcp.config = self.tcp;
+        let (min, max) = self.ephemeral_ports;
+        net.tcp.ephemeral.set_range(min, max).map_err(|_| {
+            io::Error::new(ErrorKind::InvalidInput, format!("ephemeral_ports: bad range {min}-{max}"))
+        })?;
+        if let Some(dir) = &self.trace_dir {
+            net.tcp.set_observer(Box::new(JsonLinesTracer::new(dir)?));
+        }
+        Ok(net)
+    }
+
+    // Opens the TAP device for `iface` and builds its stack.
+    pub fn open(&self, iface: &Interface) -> Result<NetStack<Tap>, StackError> {
+        self.stack(iface, Tap::open(&iface.name)?)
+    }
+}
+
+// "10.0.0.0/24"; a bare address is a /32.
+fn parse_prefix(s: &str) -> Result<([u8; 4], u8), StackError> {
+    let (addr, len) = s.split_once('/').unwrap_or((s, "32"));
+    let len = len.parse::<u8>().ok().filter(|&l| l <= 32).ok_or_else(|| StackError::BadAddress(s.to_string()))?;
+    Ok((arp::Ipv4::parse(addr)?.0, len))
+}
+
+// "49152-65535", checked the way EphemeralPorts::set_range() will.
+fn parse_port_range(s: &str) -> Result<(u16, u16), String> {
+    let bad = || format!("ephemeral_ports: expected MIN-MAX, got {s:?}");
+    let (min, max) = s.split_once('-').ok_or_else(bad)?;
+    let (min, max) = (min.trim().parse().map_err(|_| bad())?, max.trim().parse().map_err(|_| bad())?);
+    EphemeralPorts::new().set_range(min, max).map_err(|_| bad())?;
+    Ok((min, max))
+}
+
+fn set_tcp(c: &mut TcpConfig, key: &str, value: &str) -> Result<(), String> {
+    fn num<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+        value.parse().map_err(|_| format!("{key}: expected a number, got {value:?}"))
+    }
+    fn nonzero<T: std::str::FromStr + Default + PartialEq>(key: &str, value: &str) -> Result<T, String> {
+        let n = num(key, value)?;
+        if n == T::default() {
+            return Err(format!("{key}: must be above 0"));
+        }
+        Ok(n)
+    }
+    match key {
+        "mss" => {
+            c.mss = nonzero(key, value)?;
+            if c.mss > MAX_MSS {
+                return Err(format!("mss: must be at most {MAX_MSS}"));
+            }
+        }
+        "init_cwnd_segs" => c.init_cwnd_segs = nonzero(key, value)?,
+        "init_ssthresh" => c.init_ssthresh = num(key, value)?,
+        "dupack_threshold" => c.dupack_threshold = num(key, value)?,
+        "delayed_ack_ms" => c.delayed_ack_ms = num(key, value)?,
+        "msl_ms" => c.msl_ms = num(key, value)?,
+        "rto_initial_ms" => c.rto_initial_ms = num(key, value)?,
+        "rto_min_ms" => c.rto_min_ms = num(key, value)?,
+        "rto_max_ms" => c.rto_max_ms = num(key, value)?,
+        "rcv_buf" => c.rcv_buf = nonzero(key, value)?,
+        "snd_buf" => c.snd_buf = nonzero(key, value)?,
+        "syn_retries" => c.syn_retries = num(key, value)?,
+        "synack_retries" => c.synack_retries = num(key, value)?,
+        "data_retries" => c.data_retries = num(key, value)?,
+        "timestamps" => {
+            c.timestamps = match value {
+                "on" | "yes" | "true" | "1" => true,
+                "off" | "no" | "false" | "0" => false,
+                _ => return Err(format!("timestamps: expected on or off, got {value:?}")),
+            }
+        }
+        _ => return Err(format!("unknown tcp key {key}")),
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+// Errors from setting up or running a NetStack: a device that would not
+// open or failed, an address or config file that did not parse, or a
+// packet one of the layer parsers turned away. The per-layer kinds live
+// next to their parsers.
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+use crate::arp::ArpError;
+use crate::icmp::IcmpError;
+use crate::ipv4::Ipv4Error;
+use crate::l2::ethernet::EthError;
+use crate::tcp::TcpParseError;
+
+#[derive(Debug)]
+pub enum StackError {
+    Io(io::Error),
+    Device { name: String, source: io::Error }, // opening or configuring an interface
+    BadAddress(String),                         // the text that failed to parse
+    Config { line: usize, msg: String },        // a config file line we could not use
+    Eth(EthError),
+    Arp(ArpError),
+    Ipv4(Ipv4Error),
+    Icmp(IcmpError),
+    Tcp(TcpParseError),
+}
+
+impl StackError {
+    // A packet off the wire was malformed; the stack drops it and goes on.
+    pub fn is_malformed(&self) -> bool {
+        matches!(
+            self,
+            StackError::Eth(_) | StackError::Arp(_) | StackError::Ipv4(_) | StackError::Icmp(_) | StackError::Tcp(_)
+        )
+    }
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::Io(e) => e.fmt(f),
+            StackError::Device { name, source } => write!(f, "device {name}: {source}"),
+            StackError::BadAddress(s) => write!(f, "bad address {s:?}"),
+            StackError::Config { line, msg } => write!(f, "line {line}: {msg}"),
+            StackError::Eth(e) => e.fmt(f),
+            StackError::Arp(e) => e.fmt(f),
+            StackError::Ipv4(e) => e.fmt(f),
+            StackError::Icmp(e) => e.fmt(f),
+            StackError::Tcp(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for StackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StackError::Io(e) | StackError::Device { source: e, .. } => Some(e),
+            StackError::BadAddress(_) | StackError::Config { .. } => None,
+            StackError::Eth(e) => Some(e),
+            StackError::Arp(e) => Some(e),
+            StackError::Ipv4(e) => Some(e),
+            StackError::Icmp(e) => Some(e),
+            StackError::Tcp(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for StackError {
+    fn from(e: io::Error) -> Self {
+        StackError::Io(e)
+    }
+}
+
+impl From<EthError> for StackError {
+    fn from(e: EthError) -> Self {
+        StackError::Eth(e)
+    }
+}
+
+impl From<ArpError> for StackError {
+    fn from(e: ArpError) -> Self {
+        StackError::Arp(e)
+    }
+}
+
+impl From<Ipv4Error> for StackError {
+    fn from(e: Ipv4Error) -> Self {
+        StackError::Ipv4(e)
+    }
+}
+
+impl From<IcmpError> for StackError {
+    fn from(e: IcmpError) -> Self {
+        StackError::Icmp(e)
+    }
+}
+
+impl From<TcpParseError> for StackError {
+    fn from(e: TcpParseError) -> Self {
+        StackError::Tcp(e)
+    }
+}
+
+impl From<StackError> for io::Error {
+    fn from(e: StackError) -> io::Error {
+        match e {
+            StackError::Io(e) => e,
+            StackError::Device { .. } => io::Error::other(e),
+            StackError::BadAddress(_) | StackError::Config { .. } => io::Error::new(ErrorKind::InvalidInput, e),
+            _ => io::Error::new(ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -1,4 +0,0 @@
-fn main() {
-    // Delegate to main binary (same behavior) so `cargo run --example echo_server` also works.
-    tcp_stack::main()
-}
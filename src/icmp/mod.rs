@@ -1,11 +1,65 @@
+use std::fmt;
+
 use crate::util::checksum::csum16;
 
 pub const ICMP_ECHO_REQUEST: u8 = 8;
 pub const ICMP_ECHO_REPLY:   u8 = 0;
+pub const ICMP_DEST_UNREACH: u8 = 3;
+
+// destination unreachable codes (RFC 792, RFC 1122 3.2.2.1)
+pub const UNREACH_NET:         u8 = 0;
+pub const UNREACH_HOST:        u8 = 1;
+pub const UNREACH_PROTOCOL:    u8 = 2;
+pub const UNREACH_PORT:        u8 = 3;
+pub const UNREACH_FRAG_NEEDED: u8 = 4;
+pub const UNREACH_NET_UNKNOWN: u8 = 6;
+pub const UNREACH_NET_TOS:     u8 = 11;
+
+// The datagram an ICMP error is about, as quoted back to us: its header
+// fields and the first bytes of its payload (8 at least, enough for ports).
+pub struct Quoted<'a> {
+    pub proto: u8,
+    pub src: [u8;4],
+    pub dst: [u8;4],
+    pub l4: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IcmpError {
+    Truncated, // shorter than its header, or a quote too short to use
+    BadChecksum,
+}
+
+impl fmt::Display for IcmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcmpError::Truncated => f.write_str("truncated ICMP message"),
+            IcmpError::BadChecksum => f.write_str("bad ICMP checksum"),
+        }
+    }
+}
+
+impl std::error::Error for IcmpError {}
+
+// The messages the stack acts on; anything else is counted and ignored.
+pub enum Icmp<'a> {
+    EchoRequest { id: u16, seq: u16, data: &'a [u8] },
+    EchoReply { id: u16, seq: u16, data: &'a [u8] },
+    Unreachable { code: u8, quoted: Quoted<'a> },
+    Other(u8),
+}
 
 pub fn build_echo_reply(id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+    build_echo(ICMP_ECHO_REPLY, id, seq, data)
+}
+
+pub fn build_echo_request(id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+    build_echo(ICMP_ECHO_REQUEST, id, seq, data)
+}
+
+fn build_echo(icmp_type: u8, id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
     let mut p = Vec::with_capacity(8 + data.len());
-    p.push(ICMP_ECHO_REPLY);
+    p.push(icmp_type);
     p.push(0); // code
     p.extend_from_slice(&[0,0]); // checksum placeholder
     p.extend_from_slice(&id.to_be_bytes());
@@ -17,10 +71,42 @@ pub fn build_echo_reply(id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
     p
 }
 
-pub fn parse_echo(pkt: &[u8]) -> Option<(u16,u16,&[u8])> {
-    if pkt.len() < 8 { return None; }
-    if pkt[0] != ICMP_ECHO_REQUEST { return None; }
-    let id = u16::from_be_bytes([pkt[4], pkt[5]]);
-    let seq= u16::from_be_bytes([pkt[6], pkt[7]]);
-    Some((id, seq, &pkt[8..]))
+pub fn parse(pkt: &[u8]) -> Result<Icmp<'_>, IcmpError> {
+    if pkt.len() < 8 || csum16(pkt) != 0 {
+        return Err(if pkt.len() < 8 { IcmpError::Truncated } else { IcmpError::BadChecksum });
+    }
+    match pkt[0] {
+        ICMP_ECHO_REQUEST => Ok(Icmp::EchoRequest {
+            id: u16::from_be_bytes([pkt[4], pkt[5]]),
+            seq: u16::from_be_bytes([pkt[6], pkt[7]]),
+            data: &pkt[8..],
+        }),
+        ICMP_ECHO_REPLY => Ok(Icmp::EchoReply {
+            id: u16::from_be_bytes([pkt[4], pkt[5]]),
+            seq: u16::from_be_bytes([pkt[6], pkt[7]]),
+            data: &pkt[8..],
+        }),
+        ICMP_DEST_UNREACH => {
+            let quoted = parse_quoted(&pkt[8..]).ok_or(IcmpError::Truncated)?;
+            Ok(Icmp::Unreachable { code: pkt[1], quoted })
+        }
+        t => Ok(Icmp::Other(t)),
+    }
+}
+
+// The IPv4 header and leading payload bytes an ICMP error carries.
+fn parse_quoted(ip: &[u8]) -> Option<Quoted<'_>> {
+    if ip.len() < 20 {
+        return None;
+    }
+    let ihl = (ip[0] & 0x0f) as usize * 4;
+    if ip[0] >> 4 != 4 || ihl < 20 || ip.len() < ihl + 8 {
+        return None;
+    }
+    Some(Quoted {
+        proto: ip[9],
+        src: ip[12..16].try_into().unwrap(),
+        dst: ip[16..20].try_into().unwrap(),
+        l4: &ip[ihl..],
+    })
 }
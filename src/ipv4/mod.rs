@@ -1,8 +1,35 @@
+use std::fmt;
+
 use crate::util::checksum::csum16;
 
 pub const IP_PROTO_ICMP: u8 = 1;
 pub const IP_PROTO_TCP:  u8 = 6;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ipv4Error {
+    Truncated,          // shorter than the header or its total length
+    BadVersion(u8),
+    UnsupportedOptions, // IHL > 5; this stack takes no IP options
+    BadLength,          // total length smaller than the header
+    BadChecksum,
+    Martian,            // multicast/broadcast source or unspecified destination
+}
+
+impl fmt::Display for Ipv4Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ipv4Error::Truncated => f.write_str("truncated IPv4 packet"),
+            Ipv4Error::BadVersion(v) => write!(f, "IP version {v}"),
+            Ipv4Error::UnsupportedOptions => f.write_str("IPv4 options not supported"),
+            Ipv4Error::BadLength => f.write_str("IPv4 total length below header length"),
+            Ipv4Error::BadChecksum => f.write_str("bad IPv4 header checksum"),
+            Ipv4Error::Martian => f.write_str("martian IPv4 address"),
+        }
+    }
+}
+
+impl std::error::Error for Ipv4Error {}
+
 #[derive(Clone, Copy)]
 pub struct Ipv4Hdr {
     pub tos: u8,
@@ -37,12 +64,15 @@ impl Ipv4Hdr {
         out.extend_from_slice(payload);
     }
 
-    pub fn parse(pkt: &[u8]) -> Option<(Ipv4Hdr, &[u8])> {
-        if pkt.len() < 20 { return None; }
+    pub fn parse(pkt: &[u8]) -> Result<(Ipv4Hdr, &[u8]), Ipv4Error> {
+        if pkt.len() < 20 { return Err(Ipv4Error::Truncated); }
         let ihl = pkt[0] & 0x0f;
-        if ihl != 5 { return None; } // no options for learning stack
+        if pkt[0] >> 4 != 4 { return Err(Ipv4Error::BadVersion(pkt[0] >> 4)); }
+        if ihl != 5 { return Err(Ipv4Error::UnsupportedOptions); } // no options for learning stack
         let tot = u16::from_be_bytes([pkt[2], pkt[3]]) as usize;
-        if pkt.len() < tot { return None; }
+        if tot < 20 { return Err(Ipv4Error::BadLength); }
+        if pkt.len() < tot { return Err(Ipv4Error::Truncated); }
+        if csum16(&pkt[..20]) != 0 { return Err(Ipv4Error::BadChecksum); }
         let hdr = Ipv4Hdr {
             tos: pkt[1],
             id: u16::from_be_bytes([pkt[4],pkt[5]]),
@@ -52,6 +82,10 @@ impl Ipv4Hdr {
             src: [pkt[12],pkt[13],pkt[14],pkt[15]],
             dst: [pkt[16],pkt[17],pkt[18],pkt[19]],
         };
-        Some((hdr, &pkt[20..tot]))
+        // martians: a multicast/broadcast source or an unspecified destination
+        if hdr.src[0] >= 224 || hdr.dst == [0; 4] {
+            return Err(Ipv4Error::Martian);
+        }
+        Ok((hdr, &pkt[20..tot]))
     }
 }
@@ -1,18 +1,43 @@
-#[derive(Clone, Copy)]
+use std::fmt;
+
+use crate::error::StackError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Mac(pub [u8;6]);
 
 impl Mac {
-    pub fn parse(s: &str) -> Self {
-        let b: Vec<u8> = s.split(':')
-            .map(|h| u8::from_str_radix(h, 16).unwrap())
-            .collect();
+    // "02:00:00:00:00:01": six hex octets separated by colons.
+    pub fn parse(s: &str) -> Result<Self, StackError> {
+        let bad = || StackError::BadAddress(s.to_string());
         let mut arr = [0u8;6];
-        arr.copy_from_slice(&b[..6]);
-        Mac(arr)
+        let mut parts = s.split(':');
+        for b in arr.iter_mut() {
+            let h = parts.next().filter(|h| (1..=2).contains(&h.len())).ok_or_else(bad)?;
+            *b = u8::from_str_radix(h, 16).map_err(|_| bad())?;
+        }
+        if parts.next().is_some() {
+            return Err(bad());
+        }
+        Ok(Mac(arr))
     }
     pub fn broadcast() -> Self { Mac([0xff;6]) }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EthError {
+    Truncated, // shorter than the 14-byte header
+}
+
+impl fmt::Display for EthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EthError::Truncated => f.write_str("frame shorter than an Ethernet header"),
+        }
+    }
+}
+
+impl std::error::Error for EthError {}
+
 pub const ETH_P_ARP: u16 = 0x0806;
 pub const ETH_P_IPV4: u16 = 0x0800;
 
@@ -30,10 +55,15 @@ pub fn build(dst: Mac, src: Mac, ethertype: u16, payload: &[u8], out: &mut Vec<u
     out.extend_from_slice(payload);
 }
 
-pub fn parse(frame: &[u8]) -> Option<(u16, &[u8;6], &[u8;6], &[u8])> {
-    if frame.len() < 14 { return None; }
+// (ethertype, dst, src, payload)
+pub type EthFrame<'a> = (u16, &'a [u8;6], &'a [u8;6], &'a [u8]);
+
+pub fn parse(frame: &[u8]) -> Result<EthFrame<'_>, EthError> {
+    if frame.len() < 14 {
+        return Err(EthError::Truncated);
+    }
     let et = u16::from_be_bytes([frame[12], frame[13]]);
     let dst = unsafe { &*(frame[0..6].as_ptr() as *const [u8;6]) };
     let src = unsafe { &*(frame[6..12].as_ptr() as *const [u8;6]) };
-    Some((et, dst, src, &frame[14..]))
+    Ok((et, dst, src, &frame[14..]))
 }
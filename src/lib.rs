@@ -0,0 +1,63 @@
+//! A user-space TCP/IP stack for learning: Ethernet, ARP, IPv4, ICMP and
+//! TCP over a TAP device (or any [`Device`]).
+//!
+//! [`NetStack`] ties one interface together. Drive it yourself with
+//! [`NetStack::poll`] and non-blocking [`TcpSocket`]s, or hand it to
+//! [`api::socket::Stack::spawn`] for blocking `std::net`-style sockets, or to
+//! [`api::aio::Runtime`] for async ones, or let [`api::server::Server`]
+//! dispatch several listening ports to their own applications. [`TcpStack`]
+//! is the protocol engine on its own, segments in and segments out.
+//!
+//! Two stacks talking over an in-memory [`pipe`], on a simulated clock:
+//!
+//! ```
+//! use tcp_stack::{pipe, Ipv4Config, ListenAddr, ListenerConfig, Mac, NetStack};
+//!
+//! let (a, b) = pipe();
+//! let ip = |last| Ipv4Config { addr: [10, 0, 0, last], prefix_len: 24, gateway: None };
+//! let mut server = NetStack::new(a, Mac([2, 0, 0, 0, 0, 1]), ip(1));
+//! let mut client = NetStack::new(b, Mac([2, 0, 0, 0, 0, 2]), ip(2));
+//!
+//! let listener = server.listen_socket(ListenAddr::any(7), ListenerConfig::default())?;
+//! let conn = client.connect_socket([10, 0, 0, 1], 7, 0)?;
+//! client.socket(conn).send_slice(b"hello", 0)?;
+//!
+//! let (mut peer, mut got) = (None, Vec::new());
+//! for now in (0..3_000).step_by(10) {
+//!     server.poll(now)?;
+//!     client.poll(now)?;
+//!     if peer.is_none() {
+//!         peer = server.socket(listener).accept().ok();
+//!     }
+//!     if let Some(p) = peer {
+//!         let mut buf = [0; 64];
+//!         if let Ok(n) = server.socket(p).recv_slice(&mut buf) {
+//!             got.extend_from_slice(&buf[..n]);
+//!         }
+//!     }
+//! }
+//! assert_eq!(got, b"hello");
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+pub mod api;
+pub mod arp;
+pub mod config;
+pub mod error;
+pub mod icmp;
+pub mod ipv4;
+pub mod l2;
+pub mod netdev;
+pub mod stack;
+pub mod tcp;
+pub mod util;
+
+pub use error::StackError;
+pub use l2::ethernet::Mac;
+pub use netdev::{pipe, Device, Pipe, Tap};
+pub use stack::{
+    EchoReply, Ipv4Config, NetStack, Readiness, Route, Socket, SocketHandle, SocketSet, TcpSocket,
+};
+pub use tcp::{
+    ConnHandle, ListenAddr, ListenerConfig, State, TcpConfig, TcpError, TcpInfo, TcpStack,
+};
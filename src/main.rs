@@ -1,25 +1,284 @@
-mod netdev;
-mod util;
-mod l2;
-mod arp;
-mod ipv4;
-mod icmp;
-mod tcp;
-mod api;
-
-use crate::netdev::tap::Tap;
-use crate::api::echo::run_echo_server;
-
-fn main() {
-    // Defaults you can tweak via env if you wish
-    let ifname = std::env::var("TAP_IF").unwrap_or_else(|_| "tap0".to_string());
-    let our_mac = std::env::var("OUR_MAC").unwrap_or_else(|_| "02:00:00:00:00:01".to_string());
-    let our_ip  = std::env::var("OUR_IP").unwrap_or_else(|_| "10.0.0.1".to_string());
-    let peer_ip = std::env::var("PEER_IP").unwrap_or_else(|_| "10.0.0.2".to_string());
-
-    println!("[stack] opening TAP {ifname} …");
-    let tap = Tap::open(&ifname).expect("open tap");
-
-    println!("[stack] starting echo server on 0.0.0.0:8080");
-    run_echo_server(tap, &our_mac, &our_ip, &peer_ip, 8080).expect("echo server");
+// The `tcp` command: runs one of a few small applications on a TAP
+// interface. Interfaces, routes, ARP entries and TCP tuning come from a
+// config file (see tcp.conf.example); without one it is tap0 as 10.0.0.1/24.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Shutdown};
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_stack::api::discard::Discard;
+use tcp_stack::api::echo::Echo;
+use tcp_stack::api::http::{Http, Request, Response};
+use tcp_stack::api::server::Server;
+use tcp_stack::api::socket::{Stack, TcpStream};
+use tcp_stack::arp;
+use tcp_stack::config::{Config, Interface};
+use tcp_stack::util::time::now_millis;
+use tcp_stack::StackError;
+
+const USAGE: &str = "\
+usage: tcp [-c FILE] [-i IFACE] COMMAND [ARGS]
+
+options:
+  -c, --config FILE      interfaces, routes, static ARP and TCP tuning
+  -i, --iface NAME       interface from the config (default: the first)
+  -h, --help             show this text
+
+commands:
+  serve [APP[:PORT]...]  echo (7), discard (9) and http (80: / and /stats)
+                         side by side; all three unless some are named
+  echo-server [PORT]     echo back whatever each connection sends (8080)
+  discard [PORT]         accept connections and throw their data away (9)
+  connect HOST PORT      stdin to a connection, the connection to stdout
+  ping [-c COUNT] HOST   ICMP echo requests, one a second (4)
+  stats [SECONDS]        answer ARP and ping for a while (10), then print counters
+";
+
+const PING_DATA: usize = 56;
+
+struct Args {
+    config: Option<String>,
+    iface: Option<String>,
+    command: String,
+    rest: Vec<String>, // the command's own arguments
+}
+
+enum Error {
+    Usage(String),
+    Config(String, StackError), // file name
+    Stack(StackError),
+}
+
+impl From<StackError> for Error {
+    fn from(e: StackError) -> Self {
+        Error::Stack(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Stack(e.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(msg) => write!(f, "{msg}\n\n{USAGE}"),
+            Error::Config(path, e) => write!(f, "{path}: {e}"),
+            Error::Stack(e) => e.fmt(f),
+        }
+    }
+}
+
+fn usage(msg: impl Into<String>) -> Error {
+    Error::Usage(msg.into())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("tcp: {e}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e @ Error::Usage(_)) => {
+            eprintln!("tcp: {e}");
+            ExitCode::from(2)
+        }
+        Err(e) => {
+            eprintln!("tcp: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Options come before the command; everything after it is the command's.
+fn parse_args(mut argv: impl Iterator<Item = String>) -> Result<Args, Error> {
+    let mut args = Args { config: None, iface: None, command: String::new(), rest: Vec::new() };
+    while let Some(a) = argv.next() {
+        match a.as_str() {
+            "-c" | "--config" => args.config = Some(argv.next().ok_or_else(|| usage("-c needs a file"))?),
+            "-i" | "--iface" => args.iface = Some(argv.next().ok_or_else(|| usage("-i needs a name"))?),
+            "-h" | "--help" => {
+                args.command = "help".to_string();
+                return Ok(args);
+            }
+            s if s.starts_with('-') => return Err(usage(format!("unknown option {s}"))),
+            _ => {
+                args.command = a;
+                args.rest = argv.collect();
+                return Ok(args);
+            }
+        }
+    }
+    Err(usage("no command given"))
+}
+
+fn run(args: &Args) -> Result<(), Error> {
+    if args.command == "help" {
+        print!("{USAGE}");
+        return Ok(());
+    }
+    let cfg = match &args.config {
+        Some(path) => Config::load(path).map_err(|e| Error::Config(path.clone(), e))?,
+        None => Config::default(),
+    };
+    let iface = cfg.interface(args.iface.as_deref())?;
+    let rest: Vec<&str> = args.rest.iter().map(String::as_str).collect();
+    match (args.command.as_str(), rest.as_slice()) {
+        ("serve", []) => serve(&cfg, iface, &[(App::Echo, 7), (App::Discard, 9), (App::Http, 80)]),
+        ("serve", specs) => {
+            let apps = specs.iter().map(|s| parse_app(s)).collect::<Result<Vec<_>, _>>()?;
+            serve(&cfg, iface, &apps)
+        }
+        ("echo-server", port @ ([] | [_])) => serve(&cfg, iface, &[(App::Echo, parse_port(port.first(), 8080)?)]),
+        ("discard", port @ ([] | [_])) => serve(&cfg, iface, &[(App::Discard, parse_port(port.first(), 9)?)]),
+        ("connect", [host, port]) => connect(&cfg, iface, parse_host(host)?, parse_port(Some(port), 0)?),
+        ("ping", ["-c", count, host]) => {
+            let count = count.parse().map_err(|_| usage(format!("bad count {count:?}")))?;
+            ping(&cfg, iface, parse_host(host)?, count)
+        }
+        ("ping", [host]) => ping(&cfg, iface, parse_host(host)?, 4),
+        ("stats", secs @ ([] | [_])) => {
+            let secs = match secs.first() {
+                Some(s) => s.parse().map_err(|_| usage(format!("bad duration {s:?}")))?,
+                None => 10,
+            };
+            stats(&cfg, iface, secs)
+        }
+        ("echo-server" | "discard" | "connect" | "ping" | "stats", _) => {
+            Err(usage(format!("wrong arguments for {}", args.command)))
+        }
+        (cmd, _) => Err(usage(format!("unknown command {cmd}"))),
+    }
+}
+
+fn parse_port(s: Option<&&str>, default: u16) -> Result<u16, Error> {
+    match s {
+        Some(s) => s.parse().ok().filter(|&p| p != 0).ok_or_else(|| usage(format!("bad port {s:?}"))),
+        None => Ok(default),
+    }
+}
+
+fn parse_host(s: &str) -> Result<[u8; 4], Error> {
+    Ok(arp::Ipv4::parse(s)?.0)
+}
+
+fn describe(iface: &Interface) -> String {
+    let ip = iface.ip;
+    format!("{} {}/{}", iface.name, Ipv4Addr::from(ip.addr), ip.prefix_len)
+}
+
+// ---- commands ----
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum App {
+    Echo,
+    Discard,
+    Http,
+}
+
+// "echo:7", "discard:9", "http:80"; the port may be left off.
+fn parse_app(spec: &str) -> Result<(App, u16), Error> {
+    let (name, port) = match spec.split_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (spec, None),
+    };
+    let (app, default) = match name {
+        "echo" => (App::Echo, 7),
+        "discard" => (App::Discard, 9),
+        "http" => (App::Http, 80),
+        _ => return Err(usage(format!("unknown application {name:?}"))),
+    };
+    Ok((app, parse_port(port.as_ref(), default)?))
+}
+
+fn serve(cfg: &Config, iface: &Interface, apps: &[(App, u16)]) -> Result<(), Error> {
+    let mut net = cfg.open(iface)?;
+    let mut server = Server::new();
+    let index: String = apps.iter().map(|(app, port)| format!("{app:?} on port {port}\n")).collect();
+    let stats = net.snmp.clone();
+    for &(app, port) in apps {
+        match app {
+            App::Echo => server.serve(&mut net, port, Echo::new())?,
+            App::Discard => server.serve(&mut net, port, Discard)?,
+            App::Http => {
+                let (index, stats) = (index.clone(), stats.clone());
+                server.serve(&mut net, port, Http::new(move |req: &Request<'_>| match (req.method, req.path) {
+                    ("GET", "/") => Response::text(200, index.clone()),
+                    ("GET", "/stats") => Response::text(200, stats.snapshot().to_string()),
+                    ("GET", _) => Response::not_found(),
+                    _ => Response::text(405, "GET only\n"),
+                }))?
+            }
+        };
+        println!("[stack] {}: {app:?} on port {port}", describe(iface));
+    }
+    server.run(&mut net)?;
+    Ok(())
+}
+
+fn connect(cfg: &Config, iface: &Interface, host: [u8; 4], port: u16) -> Result<(), Error> {
+    let _stack = Stack::spawn(cfg.open(iface)?);
+    let s = TcpStream::connect((Ipv4Addr::from(host), port))?;
+    let mut rd = s.try_clone()?;
+    let reader = thread::spawn(move || io::copy(&mut rd, &mut io::stdout()));
+    io::copy(&mut io::stdin(), &mut &s)?;
+    // our FIN; the peer's output keeps coming until it closes too
+    s.shutdown(Shutdown::Write)?;
+    reader.join().expect("reader thread")?;
+    Ok(())
+}
+
+fn ping(cfg: &Config, iface: &Interface, host: [u8; 4], count: u16) -> Result<(), Error> {
+    let mut net = cfg.open(iface)?;
+    let id = std::process::id() as u16;
+    let data = [0u8; PING_DATA];
+    let mut sent_at = vec![Instant::now(); count as usize + 1]; // by seq
+    let mut received = 0;
+    println!("PING {}: {PING_DATA} data bytes", Ipv4Addr::from(host));
+    for seq in 1..=count {
+        sent_at[seq as usize] = Instant::now();
+        net.ping(host, id, seq, &data, now_millis())?;
+        let next = Instant::now() + Duration::from_secs(1);
+        while let Some(left) = next.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+            net.dev.wait_readable(Some(left.as_millis() as u64))?;
+            net.poll(now_millis())?;
+            while let Some(r) = net.take_echo_reply() {
+                if r.id != id || r.from != host || !(1..=seq).contains(&r.seq) {
+                    continue;
+                }
+                received += 1;
+                let rtt = sent_at[r.seq as usize].elapsed().as_secs_f64() * 1000.0;
+                println!("{} bytes from {}: icmp_seq={} time={rtt:.3} ms", r.len + 8, Ipv4Addr::from(r.from), r.seq);
+            }
+        }
+    }
+    let loss = 100 * (count - received.min(count)) as u32 / count.max(1) as u32;
+    println!("--- {} ping statistics ---", Ipv4Addr::from(host));
+    println!("{count} packets transmitted, {received} received, {loss}% packet loss");
+    Ok(())
+}
+
+fn stats(cfg: &Config, iface: &Interface, secs: u64) -> Result<(), Error> {
+    let mut net = cfg.open(iface)?;
+    println!("[stack] {}: collecting for {secs} s", describe(iface));
+    let end = Instant::now() + Duration::from_secs(secs);
+    while let Some(left) = end.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+        net.dev.wait_readable(Some(left.as_millis() as u64))?;
+        net.poll(now_millis())?;
+        if let Some(e) = net.take_rx_error() {
+            eprintln!("[stack] dropped: {e}");
+        }
+    }
+    print!("{}", net.snmp.snapshot());
+    io::stdout().flush()?;
+    Ok(())
 }
@@ -1 +1,74 @@
+pub mod pipe;
 pub mod tap;
+
+use std::io::Result;
+use std::sync::Arc;
+
+pub use pipe::{pipe, Pipe};
+pub use tap::Tap;
+
+// A link that moves whole Ethernet frames; Tap is the real one.
+pub trait Device {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize>;
+    fn send(&mut self, frame: &[u8]) -> Result<usize>;
+    // Waits until a frame can be read or `timeout_ms` passes (None = forever).
+    fn wait_readable(&self, timeout_ms: Option<u64>) -> Result<bool>;
+    // A handle for waiting on the device without holding it, e.g. while
+    // the stack that owns it is locked by someone else; None if the device
+    // has none.
+    fn waiter(&self) -> Option<Arc<dyn Waiter>> {
+        None
+    }
+}
+
+// wait_readable() from outside the owner, which another thread can cut
+// short with wake(). A wake() with nobody waiting ends the next wait.
+pub trait Waiter: Send + Sync {
+    fn wait(&self, timeout_ms: Option<u64>) -> Result<()>;
+    fn wake(&self);
+}
+
+impl<T: Device + ?Sized> Device for Box<T> {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).recv(buf)
+    }
+    fn send(&mut self, frame: &[u8]) -> Result<usize> {
+        (**self).send(frame)
+    }
+    fn wait_readable(&self, timeout_ms: Option<u64>) -> Result<bool> {
+        (**self).wait_readable(timeout_ms)
+    }
+    fn waiter(&self) -> Option<Arc<dyn Waiter>> {
+        (**self).waiter()
+    }
+}
+
+impl Device for tap::Tap {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        tap::Tap::recv(self, buf)
+    }
+    fn send(&mut self, frame: &[u8]) -> Result<usize> {
+        tap::Tap::send(self, frame)
+    }
+    fn wait_readable(&self, timeout_ms: Option<u64>) -> Result<bool> {
+        tap::Tap::wait_readable(self, timeout_ms)
+    }
+    fn waiter(&self) -> Option<Arc<dyn Waiter>> {
+        Some(Arc::new(tap::Tap::waiter(self)))
+    }
+}
+
+impl Device for pipe::Pipe {
+    fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        pipe::Pipe::recv(self, buf)
+    }
+    fn send(&mut self, frame: &[u8]) -> Result<usize> {
+        pipe::Pipe::send(self, frame)
+    }
+    fn wait_readable(&self, timeout_ms: Option<u64>) -> Result<bool> {
+        pipe::Pipe::wait_readable(self, timeout_ms)
+    }
+    fn waiter(&self) -> Option<Arc<dyn Waiter>> {
+        Some(Arc::new(pipe::Pipe::waiter(self)))
+    }
+}
@@ -0,0 +1,83 @@
+// An in-memory Ethernet link between two devices, for tests, examples and
+// benchmarks that should not need a TAP interface or root.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use super::Waiter;
+
+#[derive(Default)]
+struct Queue {
+    frames: Mutex<VecDeque<Vec<u8>>>,
+    ready: Condvar,
+    woken: AtomicBool, // PipeWaiter::wake() since its last wait
+}
+
+pub struct Pipe {
+    rx: Arc<Queue>,
+    tx: Arc<Queue>,
+}
+
+// Two ends of one link: what one end sends, the other receives.
+pub fn pipe() -> (Pipe, Pipe) {
+    let (a, b) = (Arc::new(Queue::default()), Arc::new(Queue::default()));
+    (Pipe { rx: a.clone(), tx: b.clone() }, Pipe { rx: b, tx: a })
+}
+
+impl Pipe {
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let f = self.rx.frames.lock().unwrap().pop_front().ok_or(ErrorKind::WouldBlock)?;
+        let n = f.len().min(buf.len());
+        buf[..n].copy_from_slice(&f[..n]);
+        Ok(n)
+    }
+
+    pub fn send(&mut self, frame: &[u8]) -> Result<usize> {
+        self.tx.frames.lock().unwrap().push_back(frame.to_vec());
+        self.tx.ready.notify_all();
+        Ok(frame.len())
+    }
+
+    pub fn wait_readable(&self, timeout_ms: Option<u64>) -> Result<bool> {
+        let frames = self.rx.frames.lock().unwrap();
+        let frames = match timeout_ms {
+            None => self.rx.ready.wait_while(frames, |q| q.is_empty()).unwrap(),
+            Some(ms) => {
+                let d = Duration::from_millis(ms);
+                self.rx.ready.wait_timeout_while(frames, d, |q| q.is_empty()).unwrap().0
+            }
+        };
+        Ok(!frames.is_empty())
+    }
+
+    pub fn waiter(&self) -> PipeWaiter {
+        PipeWaiter { rx: self.rx.clone() }
+    }
+}
+
+pub struct PipeWaiter {
+    rx: Arc<Queue>,
+}
+
+impl Waiter for PipeWaiter {
+    fn wait(&self, timeout_ms: Option<u64>) -> Result<()> {
+        let frames = self.rx.frames.lock().unwrap();
+        let idle = |q: &mut VecDeque<Vec<u8>>| q.is_empty() && !self.rx.woken.load(Ordering::Relaxed);
+        let _frames = match timeout_ms {
+            None => self.rx.ready.wait_while(frames, idle).unwrap(),
+            Some(ms) => self.rx.ready.wait_timeout_while(frames, Duration::from_millis(ms), idle).unwrap().0,
+        };
+        self.rx.woken.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn wake(&self) {
+        // under the lock, so a wait() between its check and its sleep still hears it
+        let _frames = self.rx.frames.lock().unwrap();
+        self.rx.woken.store(true, Ordering::Relaxed);
+        self.rx.ready.notify_all();
+    }
+}
@@ -1,7 +1,10 @@
 use std::fs::{OpenOptions, File};
 use std::io::{Read, Write, Result};
-use std::os::fd::{AsRawFd, RawFd};
-use std::os::unix::io::FromRawFd;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::sync::Arc;
+
+use super::Waiter;
+use crate::error::StackError;
 
 #[repr(C)]
 struct IfReq {
@@ -10,21 +13,37 @@ struct IfReq {
     _pad: [u8; 22], // enough to match size (varies by arch; keep simple)
 }
 
+#[repr(C)]
+struct PollFd {
+    fd: RawFd,
+    events: i16,
+    revents: i16,
+}
+
 const TUNSETIFF: u64 = 0x400454ca;
 const IFF_TAP: u16 = 0x0002;
 const IFF_NO_PI: u16 = 0x1000;
+const POLLIN: i16 = 0x0001;
+const EFD_CLOEXEC: i32 = 0o2000000;
+const EFD_NONBLOCK: i32 = 0o4000;
 
 unsafe extern "C" {
     fn ioctl(fd: RawFd, request: u64, argp: *mut IfReq) -> i32;
+    fn poll(fds: *mut PollFd, nfds: std::ffi::c_ulong, timeout: i32) -> i32;
+    fn eventfd(initval: u32, flags: i32) -> i32;
 }
 
 pub struct Tap {
-    f: File,
+    f: Arc<File>,
+    wake: Arc<File>, // eventfd that ends a TapWaiter's poll
 }
 
 impl Tap {
-    pub fn open(name: &str) -> Result<Tap> {
-        let f = OpenOptions::new().read(true).write(true).open("/dev/net/tun")?;
+    // Attaches to TAP interface `name`; needs CAP_NET_ADMIN or an interface
+    // created for this user.
+    pub fn open(name: &str) -> std::result::Result<Tap, StackError> {
+        let device = |source| StackError::Device { name: name.to_string(), source };
+        let f = OpenOptions::new().read(true).write(true).open("/dev/net/tun").map_err(device)?;
         let fd = f.as_raw_fd();
 
         let mut ifr = IfReq {
@@ -38,18 +57,73 @@ impl Tap {
 
         let rc = unsafe { ioctl(fd, TUNSETIFF, &mut ifr as *mut _) };
         if rc < 0 {
-            panic!("ioctl TUNSETIFF failed (are you root? does {name} exist?)");
+            return Err(device(std::io::Error::last_os_error()));
+        }
+
+        let efd = unsafe { eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) };
+        if efd < 0 {
+            return Err(device(std::io::Error::last_os_error()));
         }
+        // Safety: eventfd() just returned this descriptor and nothing else owns it
+        let wake = unsafe { File::from_raw_fd(efd) };
 
         // Safety: fd owned by File f; we keep f
-        Ok(Tap { f })
+        Ok(Tap { f: Arc::new(f), wake: Arc::new(wake) })
+    }
+
+    // Waits until a frame can be read or `timeout_ms` passes (None = forever).
+    pub fn wait_readable(&self, timeout_ms: Option<u64>) -> Result<bool> {
+        let mut pfd = [PollFd { fd: self.f.as_raw_fd(), events: POLLIN, revents: 0 }];
+        poll_fds(&mut pfd, timeout_ms)?;
+        Ok((pfd[0].revents & POLLIN) != 0)
+    }
+
+    pub fn waiter(&self) -> TapWaiter {
+        TapWaiter { f: self.f.clone(), wake: self.wake.clone() }
     }
 
     pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        self.f.read(buf)
+        (&*self.f).read(buf)
     }
 
     pub fn send(&mut self, buf: &[u8]) -> Result<usize> {
-        self.f.write(buf)
+        (&*self.f).write(buf)
+    }
+}
+
+pub struct TapWaiter {
+    f: Arc<File>,
+    wake: Arc<File>,
+}
+
+impl Waiter for TapWaiter {
+    fn wait(&self, timeout_ms: Option<u64>) -> Result<()> {
+        let mut pfd = [
+            PollFd { fd: self.f.as_raw_fd(), events: POLLIN, revents: 0 },
+            PollFd { fd: self.wake.as_raw_fd(), events: POLLIN, revents: 0 },
+        ];
+        poll_fds(&mut pfd, timeout_ms)?;
+        if (pfd[1].revents & POLLIN) != 0 {
+            let _ = (&*self.wake).read(&mut [0; 8]); // reset the counter
+        }
+        Ok(())
+    }
+
+    fn wake(&self) {
+        let _ = (&*self.wake).write(&1u64.to_ne_bytes());
+    }
+}
+
+// poll(2); an interrupted call counts as a timeout.
+fn poll_fds(fds: &mut [PollFd], timeout_ms: Option<u64>) -> Result<()> {
+    let timeout = timeout_ms.map_or(-1, |ms| ms.min(i32::MAX as u64) as i32);
+    let rc = unsafe { poll(fds.as_mut_ptr(), fds.len() as std::ffi::c_ulong, timeout) };
+    if rc < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+        fds.iter_mut().for_each(|p| p.revents = 0);
     }
+    Ok(())
 }
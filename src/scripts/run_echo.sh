@@ -5,7 +5,7 @@ set -euo pipefail
 cargo build
 
 # run stack (server) in nsA
-ip netns exec nsA bash -lc "OUR_MAC=02:00:00:00:00:01 OUR_IP=10.0.0.1 PEER_IP=10.0.0.2 TAP_IF=tap0 sudo -E target/debug/tcp-stack" &
+ip netns exec nsA bash -lc "sudo target/debug/tcp -c tcp.conf.example -i tap0 echo-server 8080" &
 sleep 1
 
 # client in nsB using Linux TCP
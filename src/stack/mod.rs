@@ -0,0 +1,420 @@
+// One network interface with everything above it: the device, ARP, IPv4
+// addressing, ICMP echo and the TCP engine. Applications open sockets on
+// it and call poll() whenever the device is readable or poll_delay() has
+// run out; all protocol glue happens in there.
+
+pub mod socket;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Result};
+
+use crate::arp::{self, ArpCache};
+use crate::error::StackError;
+use crate::icmp;
+use crate::ipv4::{Ipv4Error, Ipv4Hdr, IP_PROTO_ICMP, IP_PROTO_TCP};
+use crate::l2::ethernet::{self, Mac, ETH_P_ARP, ETH_P_IPV4};
+use crate::netdev::Device;
+use crate::tcp::{
+    self, ConnHandle, FourTuple, ListenAddr, ListenerConfig, Outgoing, PortError, State, TcpError,
+    TcpStack,
+};
+use crate::util::snmp::{Ctr, Snmp};
+pub use socket::{Readiness, Socket, SocketHandle, SocketSet, TcpSocket};
+
+const MAX_FRAME: usize = 2048;
+const ARP_RETRY_MS: u128 = 500; // between requests for the same address
+const DEFAULT_TTL: u8 = 64;
+const MAX_ECHO_REPLIES: usize = 64; // kept for take_echo_reply()
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ipv4Config {
+    pub addr: [u8; 4],
+    pub prefix_len: u8,             // on-link subnet
+    pub gateway: Option<[u8; 4]>,   // everything off-link goes here
+}
+
+impl Ipv4Config {
+    // Where a packet for `dst` is handed over on the link.
+    pub fn next_hop(&self, dst: [u8; 4]) -> Option<[u8; 4]> {
+        let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+        let on_link = u32::from_be_bytes(dst) & mask == u32::from_be_bytes(self.addr) & mask;
+        if on_link { Some(dst) } else { self.gateway }
+    }
+}
+
+// A static route: `dest/prefix_len` is reached through the router `via`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub dest: [u8; 4],
+    pub prefix_len: u8,
+    pub via: [u8; 4],
+}
+
+impl Route {
+    pub fn matches(&self, dst: [u8; 4]) -> bool {
+        let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+        u32::from_be_bytes(dst) & mask == u32::from_be_bytes(self.dest) & mask
+    }
+}
+
+// An ICMP echo reply that came back for ping().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EchoReply {
+    pub from: [u8; 4],
+    pub id: u16,
+    pub seq: u16,
+    pub len: usize, // payload bytes
+}
+
+pub struct NetStack<D: Device> {
+    pub dev: D,
+    pub mac: Mac,
+    pub ip: Ipv4Config,
+    pub arp: ArpCache,
+    pub tcp: TcpStack,
+    pub sockets: SocketSet,
+    pub routes: Vec<Route>, // tried before the subnet and gateway of `ip`; longest prefix wins
+    pub snmp: Snmp,         // shared with `tcp`; see util::snmp
+    echo_replies: VecDeque<EchoReply>,
+    arp_asked: HashMap<[u8; 4], u128>, // unresolved next hops, last request time
+    rx: Vec<u8>,
+    rx_error: Option<StackError>, // the latest malformed packet, for take_rx_error()
+}
+
+impl<D: Device> NetStack<D> {
+    pub fn new(dev: D, mac: Mac, ip: Ipv4Config) -> Self {
+        let tcp = TcpStack::new();
+        Self {
+            dev,
+            mac,
+            ip,
+            arp: ArpCache::new(arp::Ipv4(ip.addr), mac),
+            snmp: tcp.snmp.clone(),
+            tcp,
+            sockets: SocketSet::new(),
+            routes: Vec::new(),
+            echo_replies: VecDeque::new(),
+            arp_asked: HashMap::new(),
+            rx: vec![0; MAX_FRAME],
+            rx_error: None,
+        }
+    }
+
+    // Type-erases the device, e.g. to hand the stack to another thread.
+    pub fn boxed(self) -> NetStack<Box<dyn Device + Send>>
+    where
+        D: Send + 'static,
+    {
+        NetStack {
+            dev: Box::new(self.dev),
+            mac: self.mac,
+            ip: self.ip,
+            arp: self.arp,
+            tcp: self.tcp,
+            sockets: self.sockets,
+            routes: self.routes,
+            snmp: self.snmp,
+            echo_replies: self.echo_replies,
+            arp_asked: self.arp_asked,
+            rx: self.rx,
+            rx_error: self.rx_error,
+        }
+    }
+
+    // Handles every frame already waiting on the device, then the TCP
+    // timers that are due. Never blocks. Returns the sockets whose
+    // readiness changed since the last poll. Malformed packets are dropped
+    // (see take_rx_error()); only device errors fail the call.
+    pub fn poll(&mut self, now_ms: u128) -> Result<Vec<(SocketHandle, Readiness)>> {
+        let mut rx = std::mem::take(&mut self.rx);
+        let res = self.drain(&mut rx, now_ms);
+        self.rx = rx;
+        res?;
+        let out = self.tcp.on_timer(now_ms);
+        self.transmit(out, now_ms)?;
+        self.arp.gc();
+        Ok(self.sockets.poll(&self.tcp))
+    }
+
+    fn drain(&mut self, rx: &mut [u8], now_ms: u128) -> Result<()> {
+        while self.dev.wait_readable(Some(0))? {
+            let n = self.dev.recv(rx)?;
+            match self.on_frame(&rx[..n], now_ms) {
+                Err(e) if e.is_malformed() => self.rx_error = Some(e),
+                r => r?,
+            }
+        }
+        Ok(())
+    }
+
+    // Why the most recent bad packet was dropped, if one was since the last
+    // call. The snmp counters have the totals.
+    pub fn take_rx_error(&mut self) -> Option<StackError> {
+        self.rx_error.take()
+    }
+
+    // Milliseconds until poll() has timer work; None when nothing is pending.
+    pub fn poll_delay(&self, now_ms: u128) -> Option<u64> {
+        self.tcp.poll_delay(now_ms)
+    }
+
+    // Sleeps until a frame arrives or poll_delay() runs out.
+    pub fn wait(&self, now_ms: u128) -> Result<()> {
+        self.dev.wait_readable(self.poll_delay(now_ms)).map(|_| ())
+    }
+
+    // Where a packet for `dst` is handed over on the link: the most specific
+    // static route, else the interface's own subnet and gateway.
+    pub fn next_hop(&self, dst: [u8; 4]) -> Option<[u8; 4]> {
+        let subnet = Route { dest: self.ip.addr, prefix_len: self.ip.prefix_len, via: dst };
+        match self.routes.iter().filter(|r| r.matches(dst)).max_by_key(|r| r.prefix_len) {
+            Some(r) if !subnet.matches(dst) || r.prefix_len > subnet.prefix_len => Some(r.via),
+            _ => self.ip.next_hop(dst),
+        }
+    }
+
+    // ---- ICMP echo ----
+
+    // Sends an echo request; replies show up in take_echo_reply(). Like any
+    // packet, the first one to an unresolved neighbour is lost to ARP.
+    pub fn ping(&mut self, dst: [u8; 4], id: u16, seq: u16, data: &[u8], now_ms: u128) -> Result<()> {
+        let req = icmp::build_echo_request(id, seq, data);
+        self.snmp.icmp_out(icmp::ICMP_ECHO_REQUEST);
+        self.send_ip(dst, IP_PROTO_ICMP, &req, now_ms)
+    }
+
+    pub fn take_echo_reply(&mut self) -> Option<EchoReply> {
+        self.echo_replies.pop_front()
+    }
+
+    // ---- socket handles ----
+
+    pub fn listen_socket(&mut self, addr: ListenAddr, cfg: ListenerConfig) -> Result<SocketHandle> {
+        if !self.listen(addr, cfg) {
+            return Err(ErrorKind::AddrInUse.into());
+        }
+        Ok(self.sockets.add(Socket::Listener(addr)))
+    }
+    pub fn connect_socket(&mut self, remote_ip: [u8; 4], remote_port: u16, now_ms: u128) -> Result<SocketHandle> {
+        let c = self.connect(remote_ip, remote_port, now_ms)?;
+        Ok(self.sockets.add(Socket::Conn(c)))
+    }
+    pub fn socket(&mut self, h: SocketHandle) -> TcpSocket<'_, D> {
+        TcpSocket::new(self, h)
+    }
+    // Frees the handle: a listener stops listening, a connection is closed.
+    pub fn remove_socket(&mut self, h: SocketHandle, now_ms: u128) -> Result<()> {
+        let out = match self.sockets.remove(h) {
+            Some(Socket::Listener(a)) => self.tcp.unlisten(a, now_ms),
+            Some(Socket::Conn(c)) => {
+                let out = self.tcp.close(c, now_ms);
+                self.tcp.release(c);
+                out
+            }
+            None => return Ok(()),
+        };
+        self.transmit(out, now_ms)
+    }
+
+    // ---- connections by ConnHandle ----
+
+    pub fn listen(&mut self, addr: ListenAddr, cfg: ListenerConfig) -> bool {
+        self.tcp.listen_on(addr, cfg)
+    }
+    pub fn accept(&mut self, addr: ListenAddr) -> Option<ConnHandle> {
+        self.tcp.accept(addr)
+    }
+    pub fn connect(&mut self, remote_ip: [u8; 4], remote_port: u16, now_ms: u128) -> Result<ConnHandle> {
+        let (h, out) = self
+            .tcp
+            .connect(self.ip.addr, remote_ip, remote_port, now_ms)
+            .map_err(|_: PortError| std::io::Error::from(std::io::ErrorKind::AddrNotAvailable))?;
+        self.transmit(out, now_ms)?;
+        Ok(h)
+    }
+    pub fn recv(&mut self, h: ConnHandle, buf: &mut [u8]) -> usize {
+        self.tcp.recv(h, buf)
+    }
+    // Queues what fits in the send buffer; returns how much that was.
+    pub fn send(&mut self, h: ConnHandle, data: &[u8], now_ms: u128) -> Result<usize> {
+        let (n, out) = self.tcp.send_app(h, data, now_ms);
+        self.transmit(out, now_ms)?;
+        Ok(n)
+    }
+    // Honours the connection's linger setting; see TcpStack::set_linger().
+    pub fn close(&mut self, h: ConnHandle, now_ms: u128) -> Result<()> {
+        let out = self.tcp.close(h, now_ms);
+        self.transmit(out, now_ms)
+    }
+    // FIN after the queued data, whatever the linger setting.
+    pub fn shutdown(&mut self, h: ConnHandle, now_ms: u128) -> Result<()> {
+        let out = self.tcp.shutdown(h, now_ms);
+        self.transmit(out, now_ms)
+    }
+    // RST now; queued data is thrown away.
+    pub fn abort(&mut self, h: ConnHandle, now_ms: u128) -> Result<()> {
+        let out = self.tcp.abort(h, now_ms);
+        self.transmit(out, now_ms)
+    }
+    pub fn state(&self, h: ConnHandle) -> Option<State> {
+        self.tcp.state(h)
+    }
+
+    // Sends segments produced by a TcpStack call made directly on `tcp`.
+    pub fn transmit(&mut self, out: Outgoing, now_ms: u128) -> Result<()> {
+        for (meta, seg) in out {
+            let bytes = tcp::serialize_tcp(&seg, meta.ip_src, meta.ip_dst);
+            self.send_ip(meta.ip_dst, IP_PROTO_TCP, &bytes, now_ms)?;
+        }
+        Ok(())
+    }
+
+    // ---- input ----
+
+    fn on_frame(&mut self, frame: &[u8], now_ms: u128) -> std::result::Result<(), StackError> {
+        let (et, dst, _src, payload) = ethernet::parse(frame).inspect_err(|_| self.snmp.inc(Ctr::EthInErrors))?;
+        self.snmp.inc(match et {
+            ETH_P_IPV4 => Ctr::EthInIpv4,
+            ETH_P_ARP => Ctr::EthInArp,
+            _ => Ctr::EthInOther,
+        });
+        if dst != &self.mac.0 && dst != &Mac::broadcast().0 {
+            return Ok(());
+        }
+        match et {
+            ETH_P_ARP => self.on_arp(payload),
+            ETH_P_IPV4 => self.on_ipv4(payload, now_ms),
+            _ => Ok(()),
+        }
+    }
+
+    fn on_arp(&mut self, payload: &[u8]) -> std::result::Result<(), StackError> {
+        let (op, smac, sip, _tmac, tip) = arp::parse(payload).inspect_err(|_| self.snmp.inc(Ctr::ArpInErrors))?;
+        self.snmp.inc(if op == 1 { Ctr::ArpInRequests } else { Ctr::ArpInReplies });
+        self.arp.insert(sip, smac);
+        self.arp_asked.remove(&sip.0);
+        if op == 1 && tip.0 == self.ip.addr {
+            let reply = arp::build_reply(self.mac, arp::Ipv4(self.ip.addr), smac, sip);
+            self.snmp.inc(Ctr::ArpOutReplies);
+            self.send_frame(smac, ETH_P_ARP, &reply)?;
+        }
+        Ok(())
+    }
+
+    fn on_ipv4(&mut self, payload: &[u8], now_ms: u128) -> std::result::Result<(), StackError> {
+        self.snmp.inc(Ctr::IpInReceives);
+        let (ip, l4) = Ipv4Hdr::parse(payload).inspect_err(|e| {
+            self.snmp.inc(if *e == Ipv4Error::Martian { Ctr::IpInAddrErrors } else { Ctr::IpInHdrErrors })
+        })?;
+        if ip.dst != self.ip.addr {
+            self.snmp.inc(Ctr::IpInAddrErrors);
+            return Ok(());
+        }
+        match ip.proto {
+            IP_PROTO_ICMP => self.on_icmp(ip.src, l4, now_ms)?,
+            IP_PROTO_TCP => {
+                let seg = tcp::parse_tcp(ip.src, ip.dst, l4).inspect_err(|_| self.snmp.inc(Ctr::TcpInErrs))?;
+                // demux key (peer -> us)
+                let key = FourTuple {
+                    src_ip: ip.src,
+                    dst_ip: ip.dst,
+                    src_port: seg.src_port,
+                    dst_port: seg.dst_port,
+                };
+                let out = self.tcp.on_segment(now_ms, key, &seg);
+                self.transmit(out, now_ms)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn on_icmp(&mut self, src: [u8; 4], l4: &[u8], now_ms: u128) -> std::result::Result<(), StackError> {
+        let msg = icmp::parse(l4).inspect_err(|_| self.snmp.inc(Ctr::IcmpInErrors))?;
+        self.snmp.icmp_in(l4[0]);
+        match msg {
+            icmp::Icmp::EchoRequest { id, seq, data } => {
+                let reply = icmp::build_echo_reply(id, seq, data);
+                self.snmp.icmp_out(icmp::ICMP_ECHO_REPLY);
+                self.send_ip(src, IP_PROTO_ICMP, &reply, now_ms)?;
+            }
+            icmp::Icmp::EchoReply { id, seq, data } => {
+                if self.echo_replies.len() == MAX_ECHO_REPLIES {
+                    self.echo_replies.pop_front();
+                }
+                self.echo_replies.push_back(EchoReply { from: src, id, seq, len: data.len() });
+            }
+            icmp::Icmp::Unreachable { code, quoted } => self.on_unreachable(code, &quoted, now_ms),
+            icmp::Icmp::Other(_) => {}
+        }
+        Ok(())
+    }
+
+    // An ICMP destination unreachable about a segment we sent.
+    fn on_unreachable(&mut self, code: u8, q: &icmp::Quoted<'_>, now_ms: u128) {
+        if q.proto != IP_PROTO_TCP || q.src != self.ip.addr {
+            return;
+        }
+        let port = |i: usize| u16::from_be_bytes([q.l4[i], q.l4[i + 1]]);
+        let seq = u32::from_be_bytes([q.l4[4], q.l4[5], q.l4[6], q.l4[7]]);
+        // our connections are keyed peer -> us
+        let key = FourTuple { src_ip: q.dst, dst_ip: q.src, src_port: port(2), dst_port: port(0) };
+        if let Some((err, hard)) = unreachable_error(code) {
+            self.tcp.on_unreachable(now_ms, key, seq, err, hard);
+        }
+    }
+
+    // ---- output ----
+
+    // Best effort: without a MAC for the next hop the packet is dropped and
+    // an ARP request goes out instead; TCP retransmits once it resolves.
+    fn send_ip(&mut self, dst: [u8; 4], proto: u8, payload: &[u8], now_ms: u128) -> Result<()> {
+        let Some(hop) = self.next_hop(dst) else {
+            return Ok(()); // no route
+        };
+        let Some(mac) = self.arp.lookup(arp::Ipv4(hop)) else {
+            return self.ask_arp(hop, now_ms);
+        };
+        let hdr = Ipv4Hdr { tos: 0, id: 0, flags_frag: 0, ttl: DEFAULT_TTL, proto, src: self.ip.addr, dst };
+        let mut ipb = Vec::with_capacity(20 + payload.len());
+        hdr.serialize(payload, &mut ipb);
+        self.snmp.inc(Ctr::IpOutRequests);
+        self.send_frame(mac, ETH_P_IPV4, &ipb)
+    }
+
+    fn ask_arp(&mut self, hop: [u8; 4], now_ms: u128) -> Result<()> {
+        if self.arp_asked.get(&hop).is_some_and(|&t| now_ms < t + ARP_RETRY_MS) {
+            return Ok(());
+        }
+        self.arp_asked.insert(hop, now_ms);
+        let req = arp::build_request(self.mac, arp::Ipv4(self.ip.addr), arp::Ipv4(hop));
+        self.snmp.inc(Ctr::ArpOutRequests);
+        self.send_frame(Mac::broadcast(), ETH_P_ARP, &req)
+    }
+
+    fn send_frame(&mut self, dst: Mac, ethertype: u16, payload: &[u8]) -> Result<()> {
+        self.snmp.inc(match ethertype {
+            ETH_P_IPV4 => Ctr::EthOutIpv4,
+            ETH_P_ARP => Ctr::EthOutArp,
+            _ => Ctr::EthOutOther,
+        });
+        let mut frame = Vec::with_capacity(14 + payload.len());
+        ethernet::build(dst, self.mac, ethertype, payload, &mut frame);
+        self.dev.send(&frame)?;
+        Ok(())
+    }
+}
+
+// What an unreachable code means to TCP. RFC 1122 4.2.3.9: protocol and
+// port unreachable are hard errors that abort the connection; the rest are
+// soft. Fragmentation needed is for path MTU discovery: None.
+fn unreachable_error(code: u8) -> Option<(TcpError, bool)> {
+    match code {
+        icmp::UNREACH_PROTOCOL | icmp::UNREACH_PORT => Some((TcpError::Refused, true)),
+        icmp::UNREACH_FRAG_NEEDED => None,
+        icmp::UNREACH_NET | icmp::UNREACH_NET_UNKNOWN | icmp::UNREACH_NET_TOS => {
+            Some((TcpError::NetUnreachable, false))
+        }
+        _ => Some((TcpError::HostUnreachable, false)),
+    }
+}
@@ -0,0 +1,229 @@
+// Non-blocking socket handles, in the style of smoltcp's SocketSet. A
+// handle names either a listener or one connection; operations that would
+// block fail with WouldBlock, and NetStack::poll() reports the handles
+// whose readiness changed so an event loop knows what to look at.
+
+use std::io::{self, ErrorKind};
+
+use super::NetStack;
+use crate::netdev::Device;
+use crate::tcp::{ConnHandle, ListenAddr, State, TcpError, TcpStack};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SocketHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Socket {
+    Listener(ListenAddr),
+    Conn(ConnHandle),
+}
+
+// Level-triggered readiness; `readable` also covers a pending accept() and
+// end of stream, so a read is never left waiting for nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Readiness {
+    pub readable: bool,
+    pub writable: bool,
+    pub closed: bool, // the connection is gone from the stack
+}
+
+struct Slot {
+    sock: Socket,
+    last: Readiness, // as of the last poll()
+}
+
+#[derive(Default)]
+pub struct SocketSet {
+    slots: Vec<Option<Slot>>,
+}
+
+impl SocketSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, sock: Socket) -> SocketHandle {
+        let slot = Some(Slot { sock, last: Readiness::default() });
+        match self.slots.iter().position(|s| s.is_none()) {
+            Some(i) => {
+                self.slots[i] = slot;
+                SocketHandle(i)
+            }
+            None => {
+                self.slots.push(slot);
+                SocketHandle(self.slots.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, h: SocketHandle) -> Option<Socket> {
+        self.slots.get_mut(h.0)?.take().map(|s| s.sock)
+    }
+
+    pub fn get(&self, h: SocketHandle) -> Option<Socket> {
+        self.slots.get(h.0)?.as_ref().map(|s| s.sock)
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = SocketHandle> + '_ {
+        self.slots.iter().enumerate().filter(|(_, s)| s.is_some()).map(|(i, _)| SocketHandle(i))
+    }
+
+    pub fn readiness(&self, tcp: &TcpStack, h: SocketHandle) -> Readiness {
+        match self.get(h) {
+            Some(Socket::Listener(a)) => {
+                Readiness { readable: tcp.accept_pending(a) > 0, writable: false, closed: false }
+            }
+            Some(Socket::Conn(c)) => Readiness {
+                readable: tcp.recv_queue(c) > 0 || !may_recv(tcp.state(c)),
+                writable: tcp.may_send(c) && tcp.send_capacity(c) > 0,
+                closed: tcp.state(c).is_none(),
+            },
+            None => Readiness { closed: true, ..Readiness::default() },
+        }
+    }
+
+    // Handles whose readiness differs from the previous call, with the new value.
+    pub fn poll(&mut self, tcp: &TcpStack) -> Vec<(SocketHandle, Readiness)> {
+        let mut changed = Vec::new();
+        for i in 0..self.slots.len() {
+            if self.slots[i].is_none() {
+                continue;
+            }
+            let h = SocketHandle(i);
+            let r = self.readiness(tcp, h);
+            let slot = self.slots[i].as_mut().unwrap();
+            if slot.last != r {
+                slot.last = r;
+                changed.push((h, r));
+            }
+        }
+        changed
+    }
+}
+
+// More data can still arrive: the peer has not sent its FIN.
+fn may_recv(state: Option<State>) -> bool {
+    matches!(
+        state,
+        Some(State::SynSent | State::SynRcvd | State::Established | State::FinWait1 | State::FinWait2)
+    )
+}
+
+// One socket of a NetStack, borrowed for a few calls; see NetStack::socket().
+pub struct TcpSocket<'a, D: Device> {
+    net: &'a mut NetStack<D>,
+    sock: Option<Socket>,
+}
+
+impl<'a, D: Device> TcpSocket<'a, D> {
+    pub(super) fn new(net: &'a mut NetStack<D>, h: SocketHandle) -> Self {
+        let sock = net.sockets.get(h);
+        Self { net, sock }
+    }
+
+    fn conn(&self) -> io::Result<ConnHandle> {
+        match self.sock {
+            Some(Socket::Conn(c)) => Ok(c),
+            Some(Socket::Listener(_)) => Err(ErrorKind::InvalidInput.into()),
+            None => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+
+    pub fn state(&self) -> Option<State> {
+        match self.sock? {
+            Socket::Listener(_) => Some(State::Listen),
+            Socket::Conn(c) => self.net.tcp.state(c),
+        }
+    }
+    pub fn is_listening(&self) -> bool {
+        matches!(self.sock, Some(Socket::Listener(_)))
+    }
+    // A connection still in the stack and not just waiting out TIME-WAIT.
+    pub fn is_active(&self) -> bool {
+        matches!(self.sock, Some(Socket::Conn(_)))
+            && !matches!(self.state(), None | Some(State::Closed | State::TimeWait))
+    }
+
+    pub fn may_recv(&self) -> bool {
+        self.conn().is_ok_and(|c| may_recv(self.net.tcp.state(c)))
+    }
+    pub fn can_recv(&self) -> bool {
+        self.conn().is_ok_and(|c| self.net.tcp.recv_queue(c) > 0)
+    }
+    pub fn recv_queue(&self) -> usize {
+        self.conn().map_or(0, |c| self.net.tcp.recv_queue(c))
+    }
+
+    pub fn may_send(&self) -> bool {
+        self.conn().is_ok_and(|c| self.net.tcp.may_send(c))
+    }
+    pub fn can_send(&self) -> bool {
+        self.may_send() && self.send_capacity() > 0
+    }
+    // Free space in the send buffer.
+    pub fn send_capacity(&self) -> usize {
+        self.conn().map_or(0, |c| self.net.tcp.send_capacity(c))
+    }
+
+    // Why the connection ended, if it did not close normally; reported once.
+    pub fn take_error(&mut self) -> Option<TcpError> {
+        self.net.tcp.take_error(self.conn().ok()?)
+    }
+
+    // Ok(0) only at end of stream; WouldBlock while the peer may still send.
+    // Once the data is read, a connection that ended in error reports it.
+    pub fn recv_slice(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let c = self.conn()?;
+        let n = self.net.recv(c, buf);
+        if n == 0 && !buf.is_empty() {
+            if may_recv(self.net.tcp.state(c)) {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            if let Some(e) = self.net.tcp.take_error(c) {
+                return Err(e.into());
+            }
+        }
+        Ok(n)
+    }
+
+    // Queues what fits; WouldBlock when nothing does.
+    pub fn send_slice(&mut self, data: &[u8], now_ms: u128) -> io::Result<usize> {
+        let c = self.conn()?;
+        if !self.net.tcp.may_send(c) {
+            return Err(self.net.tcp.take_error(c).map_or(ErrorKind::BrokenPipe.into(), Into::into));
+        }
+        match self.net.send(c, data, now_ms)? {
+            0 if !data.is_empty() => Err(ErrorKind::WouldBlock.into()),
+            n => Ok(n),
+        }
+    }
+
+    // A listener's next connection, added to the set.
+    pub fn accept(&mut self) -> io::Result<SocketHandle> {
+        let Some(Socket::Listener(a)) = self.sock else {
+            return Err(ErrorKind::InvalidInput.into());
+        };
+        let c = self.net.accept(a).ok_or(ErrorKind::WouldBlock)?;
+        Ok(self.net.sockets.add(Socket::Conn(c)))
+    }
+
+    // Sends our FIN after the queued data, or a RST under a zero linger
+    // time; the handle stays valid.
+    pub fn close(&mut self, now_ms: u128) -> io::Result<()> {
+        let c = self.conn()?;
+        self.net.close(c, now_ms)
+    }
+
+    // Resets the connection, discarding anything not yet sent or read.
+    pub fn abort(&mut self, now_ms: u128) -> io::Result<()> {
+        let c = self.conn()?;
+        self.net.abort(c, now_ms)
+    }
+
+    // In milliseconds; see TcpStack::set_linger().
+    pub fn set_linger(&mut self, linger_ms: Option<u64>) -> io::Result<()> {
+        let c = self.conn()?;
+        self.net.tcp.set_linger(c, linger_ms);
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Tunables for the TCP engine. TcpStack carries a default; a listener can
+//! replace it for the connections it accepts, and a single connection can be
+//! given its own with TcpStack::set_config().
+//!
+//! ```
+//! use tcp_stack::{ListenAddr, ListenerConfig, TcpConfig, TcpStack};
+//!
+//! let mut tcp = TcpStack::with_config(TcpConfig { msl_ms: 30_000, ..TcpConfig::default() });
+//! // a listener for peers on a small-MTU link
+//! let small = TcpConfig { mss: 1200, init_cwnd_segs: 4, ..tcp.config };
+//! assert!(tcp.listen_on(ListenAddr::any(80), ListenerConfig { tcp: Some(small), ..ListenerConfig::default() }));
+//! assert_eq!(tcp.listener_config(ListenAddr::any(80)).mss, 1200);
+//! ```
+
+use crate::util::time::{RTO_INITIAL_MS, RTO_MAX_MS, RTO_MIN_MS};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpConfig {
+    pub mss: usize,              // the most we accept per segment; advertised in our SYN
+    pub init_cwnd_segs: usize,   // initial window in segments (RFC 6928: 10)
+    pub init_ssthresh: usize,    // bytes
+    pub dupack_threshold: u32,   // duplicate ACKs that trigger fast retransmit
+    pub delayed_ack_ms: u64,
+    pub msl_ms: u64,             // TIME-WAIT lasts 2 * MSL
+    pub rto_initial_ms: u64,     // before the first RTT sample (RFC 6298 2.1)
+    pub rto_min_ms: u64,
+    pub rto_max_ms: u64,
+    pub rcv_buf: usize,          // bounds the advertised window and reassembly
+    pub snd_buf: usize,
+    pub syn_retries: u32,        // SYN retransmissions before an active open fails
+    pub synack_retries: u32,     // SYN-ACK retransmissions before a half-open connection is dropped
+    pub data_retries: u32,       // RTO retransmissions of one segment before giving up
+    pub timestamps: bool,        // offer/accept RFC 7323 timestamps
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            mss: 1460,
+            init_cwnd_segs: 10,
+            init_ssthresh: 64 * 1024,
+            dupack_threshold: 3,
+            delayed_ack_ms: 80,
+            msl_ms: 1_000, // short for the lab; RFC 9293 suggests 2 minutes
+            rto_initial_ms: RTO_INITIAL_MS,
+            rto_min_ms: RTO_MIN_MS,
+            rto_max_ms: RTO_MAX_MS,
+            rcv_buf: 64 * 1024,
+            snd_buf: 64 * 1024,
+            syn_retries: 6,
+            synack_retries: 5,
+            data_retries: 15,
+            timestamps: true,
+        }
+    }
+}
+
+impl TcpConfig {
+    pub fn init_cwnd(&self) -> usize {
+        self.init_cwnd_segs * self.mss
+    }
+    pub fn time_wait_ms(&self) -> u128 {
+        2 * self.msl_ms as u128
+    }
+}
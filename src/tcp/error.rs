@@ -0,0 +1,67 @@
+// Why a connection ended abnormally. The stack keeps it after the Tcb is
+// gone, until the application asks (TcpStack::take_error) or lets go of
+// the connection (TcpStack::release).
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpError {
+    Reset,           // RST on a synchronized connection
+    Refused,         // RST answering our SYN, or ICMP port/protocol unreachable
+    TimedOut,        // SYN or data retransmissions, or keepalive probes, ran out
+    NetUnreachable,  // ICMP destination unreachable: no route to the network
+    HostUnreachable, // ICMP destination unreachable: host down, filtered, ...
+}
+
+impl TcpError {
+    pub fn kind(self) -> ErrorKind {
+        match self {
+            TcpError::Reset => ErrorKind::ConnectionReset,
+            TcpError::Refused => ErrorKind::ConnectionRefused,
+            TcpError::TimedOut => ErrorKind::TimedOut,
+            TcpError::NetUnreachable => ErrorKind::NetworkUnreachable,
+            TcpError::HostUnreachable => ErrorKind::HostUnreachable,
+        }
+    }
+}
+
+impl fmt::Display for TcpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TcpError::Reset => "connection reset by peer",
+            TcpError::Refused => "connection refused",
+            TcpError::TimedOut => "connection timed out",
+            TcpError::NetUnreachable => "network unreachable",
+            TcpError::HostUnreachable => "no route to host",
+        })
+    }
+}
+
+impl std::error::Error for TcpError {}
+
+impl From<TcpError> for io::Error {
+    fn from(e: TcpError) -> io::Error {
+        io::Error::new(e.kind(), e)
+    }
+}
+
+// Why parse_tcp() rejected a segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpParseError {
+    Truncated,     // shorter than 20 bytes or than its data offset
+    BadDataOffset, // header length below 20
+    BadChecksum,   // over the IPv4 pseudo-header and the segment
+}
+
+impl fmt::Display for TcpParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TcpParseError::Truncated => "truncated TCP segment",
+            TcpParseError::BadDataOffset => "TCP data offset below 5",
+            TcpParseError::BadChecksum => "bad TCP checksum",
+        })
+    }
+}
+
+impl std::error::Error for TcpParseError {}
@@ -0,0 +1,60 @@
+// TCP Fast Open (RFC 7413)
+//
+// Server side: a cookie is a keyed MAC of the client's address. A client
+// that got one in an earlier SYN-ACK can send it with data in its next SYN,
+// and the listener hands that data to the application before the handshake
+// completes. Client side: cookies learned from SYN-ACKs are cached per
+// server address and dropped again when the server ignores our SYN data.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use super::options::TfoCookie;
+
+const COOKIE_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FastOpenStats {
+    pub cookies_sent: u64, // SYN-ACKs carrying a fresh cookie
+    pub accepted: u64,     // SYNs whose data went straight to the application
+    pub rejected: u64,     // SYNs with an invalid cookie, or over the pending limit
+    pub fallbacks: u64,    // our SYN data the server did not acknowledge
+}
+
+pub struct FastOpen {
+    secret: RandomState,
+    cache: HashMap<[u8; 4], TfoCookie>,
+    pub stats: FastOpenStats,
+}
+
+impl Default for FastOpen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FastOpen {
+    pub fn new() -> Self {
+        Self { secret: RandomState::new(), cache: HashMap::new(), stats: FastOpenStats::default() }
+    }
+
+    // Cookie this host issues to `client_ip`.
+    pub fn cookie(&self, client_ip: [u8; 4]) -> TfoCookie {
+        let mac = self.secret.hash_one(client_ip).to_be_bytes();
+        TfoCookie::new(&mac[..COOKIE_LEN]).unwrap()
+    }
+
+    pub fn check(&self, client_ip: [u8; 4], c: &TfoCookie) -> bool {
+        !c.is_request() && *c == self.cookie(client_ip)
+    }
+
+    // Client-side cache, keyed by server address.
+    pub fn cached(&self, server_ip: [u8; 4]) -> Option<TfoCookie> {
+        self.cache.get(&server_ip).copied()
+    }
+    pub fn remember(&mut self, server_ip: [u8; 4], c: TfoCookie) {
+        self.cache.insert(server_ip, c);
+    }
+    pub fn forget(&mut self, server_ip: [u8; 4]) {
+        self.cache.remove(&server_ip);
+    }
+}
@@ -0,0 +1,54 @@
+// Point-in-time view of one connection, in the spirit of Linux's
+// struct tcp_info. Callers get a copy, so the Tcb layout can change
+// without breaking whoever charts these numbers.
+
+use super::{State, Tcb};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpInfo {
+    pub state: State,
+    pub srtt_ms: Option<u64>, // None until the first RTT sample
+    pub rttvar_ms: Option<u64>,
+    pub rto_ms: u64,
+    pub cwnd: usize, // bytes
+    pub ssthresh: usize,
+    pub mss: usize,
+    pub snd_wnd: u32, // peer's advertised window
+    pub rcv_wnd: u32, // ours
+    pub bytes_in_flight: usize,
+    pub bytes_sent: u64, // payload bytes, retransmissions included
+    pub bytes_acked: u64,
+    pub bytes_retrans: u64,
+    pub segs_in: u64,
+    pub segs_out: u64,
+    pub dupacks: u64,     // duplicate ACKs received over the connection's life
+    pub ooo_bytes: usize, // held in the reassembly queue
+    pub rcv_queue: usize, // in order, waiting for recv()
+    pub snd_queue: usize, // sent-but-unacked plus not yet sent
+}
+
+impl TcpInfo {
+    pub fn from_tcb(t: &Tcb) -> Self {
+        Self {
+            state: t.state,
+            srtt_ms: t.rto.srtt_ms(),
+            rttvar_ms: t.rto.rttvar_ms(),
+            rto_ms: t.rto.rto_ms,
+            cwnd: t.cwnd,
+            ssthresh: t.ssthresh,
+            mss: t.mss,
+            snd_wnd: t.snd_wnd,
+            rcv_wnd: t.rcv_wnd,
+            bytes_in_flight: t.flight,
+            bytes_sent: t.bytes_sent,
+            bytes_acked: t.bytes_acked,
+            bytes_retrans: t.bytes_retrans,
+            segs_in: t.segs_in,
+            segs_out: t.segs_out,
+            dupacks: t.dupacks_in,
+            ooo_bytes: t.ooo.bytes(),
+            rcv_queue: t.app_read.len(),
+            snd_queue: t.sendq.len(),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// Passive-open endpoints: bound address, per-listener options and the
+// queue of handshaken connections waiting for accept().
+
+use std::collections::VecDeque;
+use std::task::Waker;
+use super::{FourTuple, TcpConfig};
+
+const DEFAULT_BACKLOG: usize = 128;
+
+// Local address a listener is bound to; `ip: None` is the wildcard (0.0.0.0).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ListenAddr {
+    pub ip: Option<[u8; 4]>,
+    pub port: u16,
+}
+
+impl ListenAddr {
+    pub fn any(port: u16) -> Self {
+        Self { ip: None, port }
+    }
+    pub fn new(ip: [u8; 4], port: u16) -> Self {
+        Self { ip: Some(ip), port }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CongestionControl {
+    Reno,      // slow start + AIMD + fast retransmit
+    Unlimited, // no cwnd, only the peer's window limits sending (lab links)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keepalive {
+    pub idle_ms: u64,     // silence before the first probe
+    pub interval_ms: u64, // between unanswered probes
+    pub probes: u32,      // unanswered probes before the connection is dropped
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListenerConfig {
+    pub backlog: usize, // established connections waiting for accept()
+    pub tcp: Option<TcpConfig>, // None: the stack's TcpConfig
+    pub cc: CongestionControl,
+    pub keepalive: Option<Keepalive>,
+    pub nodelay: bool, // disable Nagle
+    pub pacing: bool,  // spread each cwnd over the RTT instead of bursting it
+    pub fastopen_queue: usize, // TFO SYNs awaiting their final ACK; 0 disables Fast Open
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            backlog: DEFAULT_BACKLOG,
+            tcp: None,
+            cc: CongestionControl::Reno,
+            keepalive: None,
+            nodelay: false,
+            pacing: false,
+            fastopen_queue: 0,
+        }
+    }
+}
+
+// What accept() hands out; names a connection in TcpStack::conns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ConnHandle(pub FourTuple);
+
+pub struct Listener {
+    pub addr: ListenAddr,
+    pub cfg: ListenerConfig,
+    pub accept_q: VecDeque<ConnHandle>,
+    pub accept_waker: Option<Waker>, // an async accept() waiting on accept_q
+    pub half_open: usize,            // its connections still in SYN-RECEIVED
+    pub cookies_sent_ms: Option<u128>, // the last SYN answered with a cookie
+}
+
+impl Listener {
+    pub fn new(addr: ListenAddr, cfg: ListenerConfig) -> Self {
+        Self { addr, cfg, accept_q: VecDeque::new(), accept_waker: None, half_open: 0, cookies_sent_ms: None }
+    }
+    pub fn accept_q_full(&self) -> bool {
+        self.accept_q.len() >= self.cfg.backlog
+    }
+}
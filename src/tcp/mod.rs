@@ -1,6 +1,39 @@
+pub mod config;
+pub mod error;
+pub mod fastopen;
+pub mod info;
+pub mod isn;
+pub mod listener;
+pub mod options;
+pub mod ports;
+pub mod reassembly;
+pub mod syncookie;
+pub mod timer;
+pub mod trace;
+
+use std::borrow::Cow;
 use std::cmp::{max, min};
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap};
+use std::task::Waker;
 use crate::util::checksum::tcp_ipv4_csum;
+use crate::util::ring::RingBuf;
+use crate::util::snmp::{Ctr, Snmp};
+pub use listener::{
+    CongestionControl, ConnHandle, Keepalive, ListenAddr, Listener, ListenerConfig,
+};
+pub use config::TcpConfig;
+pub use error::{TcpError, TcpParseError};
+pub use fastopen::FastOpenStats;
+pub use info::TcpInfo;
+pub use options::{TcpOptions, TfoCookie};
+pub use ports::PortError;
+use fastopen::FastOpen;
+use isn::IsnGen;
+use ports::EphemeralPorts;
+use reassembly::Reassembly;
+use syncookie::{SynCookieStats, SynCookies};
+use timer::TimerWheel;
+pub use trace::{DropReason, RetransmitKind, SegSummary, TcpEvent, TcpObserver};
 
 // ---------- constants ----------
 pub const TCP_FLAG_FIN: u16 = 0x01;
@@ -8,13 +41,15 @@ pub const TCP_FLAG_SYN: u16 = 0x02;
 pub const TCP_FLAG_RST: u16 = 0x04;
 pub const TCP_FLAG_PSH: u16 = 0x08;
 pub const TCP_FLAG_ACK: u16 = 0x10;
+pub const TCP_FLAG_URG: u16 = 0x20;
 
-const MAX_SEG: usize = 1460;
-const INIT_CWND: usize = 2 * MAX_SEG;
-const INIT_SSTHRESH: usize = 64 * 1024;
-const DUPACK_THRESHOLD: u32 = 3;
-const DELAYED_ACK_MS: u64 = 80;
-const TIME_WAIT_MS: u64 = 2_000;
+const SYN_BACKLOG: usize = 128;
+const MAX_TIME_WAIT: usize = 4096;
+// a reopened TIME-WAIT tuple starts this far past the old send sequence
+const REUSE_ISN_GAP: u32 = 65_536;
+const PERSIST_MAX_MS: u128 = 60_000;
+const PACING_BURST: usize = 4; // segments per paced send
+const DEFAULT_PEER_MSS: u16 = 536; // RFC 9293 3.7.1, when the SYN has no MSS option
 
 // ---------- state ----------
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -48,26 +83,34 @@ pub struct TcpSeg<'a> {
     pub ack: u32,
     pub flags: u16,
     pub wnd: u16,
+    pub urg_ptr: u16, // offset from seq of the byte after the urgent data (RFC 6093)
+    pub opts: TcpOptions,
     pub payload: &'a [u8],
 }
 
-pub fn parse_tcp(pkt: &[u8]) -> Option<TcpSeg> {
+// `src` and `dst` are the IPv4 addresses, for the pseudo-header checksum.
+pub fn parse_tcp(src: [u8; 4], dst: [u8; 4], pkt: &[u8]) -> Result<TcpSeg<'_>, TcpParseError> {
     if pkt.len() < 20 {
-        return None;
+        return Err(TcpParseError::Truncated);
     }
-    let src = u16::from_be_bytes([pkt[0], pkt[1]]);
-    let dst = u16::from_be_bytes([pkt[2], pkt[3]]);
+    if tcp_ipv4_csum(src, dst, 6, pkt) != 0 {
+        return Err(TcpParseError::BadChecksum);
+    }
+    let src_port = u16::from_be_bytes([pkt[0], pkt[1]]);
+    let dst_port = u16::from_be_bytes([pkt[2], pkt[3]]);
     let seq = u32::from_be_bytes([pkt[4], pkt[5], pkt[6], pkt[7]]);
     let ack = u32::from_be_bytes([pkt[8], pkt[9], pkt[10], pkt[11]]);
     let data_off = (pkt[12] >> 4) as usize;
     let flags = pkt[13] as u16;
     let wnd = u16::from_be_bytes([pkt[14], pkt[15]]);
+    let urg_ptr = u16::from_be_bytes([pkt[18], pkt[19]]);
     let hlen = data_off * 4;
-    if pkt.len() < hlen {
-        return None;
+    if hlen < 20 || pkt.len() < hlen {
+        return Err(if hlen < 20 { TcpParseError::BadDataOffset } else { TcpParseError::Truncated });
     }
+    let opts = TcpOptions::parse(&pkt[20..hlen]);
     let payload = &pkt[hlen..];
-    Some(TcpSeg { src_port: src, dst_port: dst, seq, ack, flags, wnd, payload })
+    Ok(TcpSeg { src_port, dst_port, seq, ack, flags, wnd, urg_ptr, opts, payload })
 }
 
 fn put_be16(v: u16, out: &mut Vec<u8>) {
@@ -84,12 +127,16 @@ pub struct WireSeg<'a> {
     pub ack: u32,
     pub flags: u16,
     pub wnd: u16,
-    pub payload: &'a [u8],
+    pub payload: Cow<'a, [u8]>,
+    pub opts: TcpOptions,
+    pub urg_ptr: u16, // only meaningful with TCP_FLAG_URG
 }
 
 pub fn serialize_tcp(h: &WireSeg, ip_src: [u8; 4], ip_dst: [u8; 4]) -> Vec<u8> {
-    let data_off = 5u8 << 4; // no options
-    let mut p = Vec::with_capacity(20 + h.payload.len());
+    let mut opts = Vec::new();
+    h.opts.write(&mut opts);
+    let data_off = ((5 + opts.len() / 4) as u8) << 4;
+    let mut p = Vec::with_capacity(20 + opts.len() + h.payload.len());
     put_be16(h.src_port, &mut p);
     put_be16(h.dst_port, &mut p);
     put_be32(h.seq, &mut p);
@@ -98,8 +145,9 @@ pub fn serialize_tcp(h: &WireSeg, ip_src: [u8; 4], ip_dst: [u8; 4]) -> Vec<u8> {
     p.push((h.flags & 0xff) as u8);
     put_be16(h.wnd, &mut p);
     p.extend_from_slice(&[0, 0]); // checksum placeholder
-    put_be16(0, &mut p); // urg ptr
-    p.extend_from_slice(h.payload);
+    put_be16(h.urg_ptr, &mut p);
+    p.extend_from_slice(&opts);
+    p.extend_from_slice(&h.payload);
 
     let c = tcp_ipv4_csum(ip_src, ip_dst, 6, &p);
     p[16] = (c >> 8) as u8;
@@ -127,7 +175,22 @@ pub struct Tcb {
     pub ssthresh: usize,
     pub dupacks: u32,
     pub ack_due_ms: u128,
-    pub timewait_until_ms: u128,
+    pub timewait_until_ms: u128, // end of TIME-WAIT, or of FIN-WAIT-2 after close()
+    pub linger: Option<u64>,     // SO_LINGER, see TcpStack::set_linger()
+    pub linger_until_ms: u128,   // after close(): reset if our FIN is not acked by then
+    pub syn_sent_ms: u128, // last (re)transmission of our SYN or SYN-ACK
+    pub persist_due_ms: u128, // zero-window probe timer, 0 = idle
+    pub persist_backoff: u32,
+    pub nodelay: bool,
+    pub pacing: bool,
+    pub pace_next_ms: u128, // paced sender may not send before this
+    pub cc: CongestionControl,
+    pub keepalive: Option<Keepalive>,
+    pub last_rx_ms: u128,
+    pub ka_probes: u32, // keepalive probes sent since last_rx_ms
+    pub retries: u32,   // SYN or RTO retransmissions without progress
+    pub soft_error: Option<TcpError>, // ICMP since the last progress; reported if we time out
+    pub fastopen: bool, // data rode on the SYN (ours, or the peer's and we took it)
 
     // RTT/RTO
     pub rto: crate::util::time::RtoCalc,
@@ -135,16 +198,44 @@ pub struct Tcb {
     // send tracking
     pub mss: usize,
     pub flight: usize,                      // bytes outstanding
-    pub sendq: VecDeque<u8>,                // app data queued
-    pub unacked: BTreeMap<u32, (usize, u128)>, // seq -> (len, sent_ms)
+    pub sendq: RingBuf,                     // app data from snd_una on, sent or not
+    pub unacked: BTreeMap<u32, SentSeg>,    // by start seq
+    pub rto_due_ms: u128,                   // retransmission timer, 0 = stopped
+    pub snd_up: Option<u32>,                // seq just past our urgent data, until acked
+    pub fin_queued: bool,                   // close() called: FIN follows the queued data
+    pub fin_seq: Option<u32>,               // our FIN, once sent
+
+    // RFC 7323 timestamps, when both ends agreed on them in the handshake
+    pub ts_ok: bool,
+    pub ts_recent: u32, // peer's latest TSval, echoed back in TSecr
 
     // receive reassembly
-    pub ooo: BTreeMap<u32, Vec<u8>>, // seq -> payload
-    pub app_read: VecDeque<u8>,      // in-order for app
+    pub ooo: Reassembly,
+    pub app_read: RingBuf,           // in-order for app; capacity bounds rcv_wnd
+
+    // urgent data (RFC 6093)
+    pub rcv_up: Option<u32>,    // peer's urgent pointer, until the stream reaches it
+    pub urg_mark: Option<usize>, // app_read offset of the last urgent byte
+    pub oob: Option<u8>,        // that byte, when pulled out of the stream
+    pub oob_inline: bool,       // leave it in the stream instead
+
+    // lifetime counters, reported through TcpInfo
+    pub bytes_sent: u64,    // payload bytes, retransmissions included
+    pub bytes_acked: u64,
+    pub bytes_retrans: u64,
+    pub segs_in: u64,
+    pub segs_out: u64,
+    pub dupacks_in: u64,
+
+    // events for the stack's observer, None while nobody listens
+    pub trace: Option<Vec<TcpEvent>>,
+
+    pub cfg: TcpConfig,
 }
 
 impl Tcb {
     pub fn new_listen(port: u16) -> Self {
+        let cfg = TcpConfig::default();
         Self {
             state: State::Listen,
             iss: 0,
@@ -157,22 +248,147 @@ impl Tcb {
             local_port: port,
             remote_port: 0,
             local_isn: 0x1234_5678,
-            cwnd: INIT_CWND,
-            ssthresh: INIT_SSTHRESH,
+            cwnd: cfg.init_cwnd(),
+            ssthresh: cfg.init_ssthresh,
             dupacks: 0,
             ack_due_ms: 0,
             timewait_until_ms: 0,
+            linger: None,
+            linger_until_ms: 0,
+            syn_sent_ms: 0,
+            persist_due_ms: 0,
+            persist_backoff: 0,
+            nodelay: false,
+            pacing: false,
+            pace_next_ms: 0,
+            cc: CongestionControl::Reno,
+            keepalive: None,
+            last_rx_ms: 0,
+            ka_probes: 0,
+            retries: 0,
+            soft_error: None,
+            fastopen: false,
             rto: crate::util::time::RtoCalc::new(),
-            mss: MAX_SEG,
+            mss: cfg.mss,
             flight: 0,
-            sendq: VecDeque::new(),
+            sendq: RingBuf::new(cfg.snd_buf),
             unacked: BTreeMap::new(),
-            ooo: BTreeMap::new(),
-            app_read: VecDeque::new(),
+            rto_due_ms: 0,
+            snd_up: None,
+            ooo: Reassembly::new(cfg.rcv_buf),
+            app_read: RingBuf::new(cfg.rcv_buf),
+            rcv_up: None,
+            urg_mark: None,
+            oob: None,
+            oob_inline: false,
+            bytes_sent: 0,
+            bytes_acked: 0,
+            bytes_retrans: 0,
+            segs_in: 0,
+            segs_out: 0,
+            dupacks_in: 0,
+            trace: None,
+            cfg,
+            fin_queued: false,
+            fin_seq: None,
+            ts_ok: false,
+            ts_recent: 0,
         }
     }
+
+    pub fn configure(&mut self, cfg: &ListenerConfig) {
+        self.cc = cfg.cc;
+        self.keepalive = cfg.keepalive;
+        self.nodelay = cfg.nodelay;
+        self.pacing = cfg.pacing;
+    }
+
+    // Applies engine tunables. The congestion window restarts at the initial
+    // window only while nothing has been sent beyond the SYN; smaller
+    // buffers still keep whatever is queued in them.
+    pub fn set_config(&mut self, cfg: &TcpConfig) {
+        self.cfg = *cfg;
+        self.rto.set_bounds(cfg.rto_initial_ms, cfg.rto_min_ms, cfg.rto_max_ms);
+        self.mss = min(self.mss, cfg.mss);
+        if matches!(self.state, State::Closed | State::Listen | State::SynSent | State::SynRcvd) {
+            self.mss = cfg.mss;
+            self.cwnd = cfg.init_cwnd();
+            self.ssthresh = cfg.init_ssthresh;
+        }
+        self.sendq.resize(cfg.snd_buf);
+        self.app_read.resize(cfg.rcv_buf);
+        self.ooo.cap = cfg.rcv_buf;
+        self.update_rcv_wnd();
+    }
+
+    // Settles the send MSS once the peer's SYN told us its limit; the
+    // initial window (RFC 6928) is counted in those segments.
+    fn set_peer_mss(&mut self, peer_mss: Option<u16>) {
+        self.mss = min(self.cfg.mss, peer_mss.unwrap_or(DEFAULT_PEER_MSS) as usize);
+        self.cwnd = self.cfg.init_cwnd_segs * self.mss;
+    }
+
+    // Advertise whatever app_read still has room for (no window scaling).
+    pub fn update_rcv_wnd(&mut self) {
+        self.rcv_wnd = min(self.app_read.free(), 65535) as u32;
+    }
+
+    pub fn emit(&mut self, ev: TcpEvent) {
+        if let Some(events) = &mut self.trace {
+            events.push(ev);
+        }
+    }
+
+    pub fn set_state(&mut self, to: State) {
+        if to != self.state {
+            self.emit(TcpEvent::State { from: self.state, to });
+            self.state = to;
+        }
+    }
+
+    fn emit_cwnd(&mut self) {
+        self.emit(TcpEvent::Cwnd { cwnd: self.cwnd, ssthresh: self.ssthresh });
+    }
+
+    // Queued bytes not yet sent (sendq starts at snd_una).
+    pub fn unsent(&self) -> usize {
+        let sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        self.sendq.len().saturating_sub(sent)
+    }
+    pub fn fin_acked(&self) -> bool {
+        self.fin_seq.is_some_and(|f| seq_before(f, self.snd_una))
+    }
+
+    // Earliest of the timers this connection has running.
+    pub fn next_deadline(&self) -> Option<u128> {
+        let established = self.state == State::Established;
+        let timewait = (self.state == State::TimeWait || self.state == State::FinWait2 && self.timewait_until_ms != 0)
+            .then_some(self.timewait_until_ms);
+        let syn = matches!(self.state, State::SynSent | State::SynRcvd).then(|| self.syn_sent_ms + self.rto.rto_ms as u128);
+        let keepalive = self.keepalive.filter(|_| established).map(|ka| {
+            self.last_rx_ms + ka.idle_ms as u128 + self.ka_probes as u128 * ka.interval_ms as u128
+        });
+        let delayed_ack = (self.ack_due_ms != 0).then_some(self.ack_due_ms);
+        let rto = (self.rto_due_ms != 0).then_some(self.rto_due_ms);
+        let persist = (self.persist_due_ms != 0).then_some(self.persist_due_ms);
+        let pacing = (self.pace_next_ms != 0 && self.unsent() > 0).then_some(self.pace_next_ms);
+        let linger = (self.linger_until_ms != 0 && !self.fin_acked()).then_some(self.linger_until_ms);
+        [timewait, syn, keepalive, delayed_ack, rto, persist, pacing, linger].into_iter().flatten().min()
+    }
+}
+
+// A sent, not yet acknowledged data segment.
+#[derive(Clone, Copy, Debug)]
+pub struct SentSeg {
+    pub len: usize,
+    pub sent_ms: u128,
+    pub retransmitted: bool, // Karn: never take an RTT sample from it
+    pub fin: bool,           // our FIN, sent on its own
 }
 
+// Segments a call produced, with the addressing each one goes out on.
+pub type Outgoing = Vec<(ConnMeta, WireSeg<'static>)>;
+
 // One connection plus its addressing
 #[derive(Clone)]
 pub struct ConnMeta {
@@ -183,19 +399,435 @@ pub struct ConnMeta {
 pub struct TcpConn {
     pub tcb: Tcb,
     pub meta: ConnMeta,
+    pub listener: Option<ListenAddr>, // passive opens: where accept() will find us
+    // async tasks waiting to read, or to write (or for connect to finish);
+    // woken whenever a segment, timer or removal touches the connection
+    pub recv_waker: Option<Waker>,
+    pub send_waker: Option<Waker>,
+    pub released: bool, // the application dropped its handle; see release()
+    half_open: bool,    // counted in its listener's half_open
+}
+
+impl TcpConn {
+    fn new(tcb: Tcb, meta: ConnMeta, listener: Option<ListenAddr>) -> Self {
+        Self { tcb, meta, listener, recv_waker: None, send_waker: None, released: false, half_open: false }
+    }
 }
 
 pub struct TcpStack {
-    pub listeners: HashMap<u16, ()>,
+    pub listeners: HashMap<ListenAddr, Listener>,
     pub conns: HashMap<FourTuple, TcpConn>,
+    // half-open (SYN-RECEIVED) connections allowed per port before SYN cookies kick in
+    pub syn_backlog: usize,
+    // connections kept in TIME-WAIT; past this, new ones are closed at once
+    pub max_time_wait: usize,
+    pub syncookies: SynCookies,
+    pub isn: IsnGen,
+    pub ephemeral: EphemeralPorts,
+    pub timers: TimerWheel<FourTuple>,
+    pub fastopen: FastOpen,
+    pub observer: Option<Box<dyn TcpObserver>>,
+    pub config: TcpConfig, // for connect() and listeners without their own
+    // connections that ended in an error the application has not seen yet
+    pub errors: HashMap<FourTuple, TcpError>,
+    pub snmp: Snmp, // the owning NetStack's counters, or its own
+}
+
+impl Default for TcpStack {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TcpStack {
     pub fn new() -> Self {
-        Self { listeners: HashMap::new(), conns: HashMap::new() }
+        Self::with_config(TcpConfig::default())
+    }
+    pub fn with_config(config: TcpConfig) -> Self {
+        Self {
+            listeners: HashMap::new(),
+            conns: HashMap::new(),
+            syn_backlog: SYN_BACKLOG,
+            max_time_wait: MAX_TIME_WAIT,
+            syncookies: SynCookies::new(),
+            isn: IsnGen::new(),
+            ephemeral: EphemeralPorts::new(),
+            timers: TimerWheel::new(0),
+            fastopen: FastOpen::new(),
+            observer: None,
+            config,
+            errors: HashMap::new(),
+            snmp: Snmp::new(),
+        }
     }
     pub fn listen(&mut self, port: u16) {
-        self.listeners.insert(port, ());
+        self.listen_on(ListenAddr::any(port), ListenerConfig::default());
+    }
+
+    // Returns false if `addr` is already bound.
+    pub fn listen_on(&mut self, addr: ListenAddr, cfg: ListenerConfig) -> bool {
+        if self.listeners.contains_key(&addr) {
+            return false;
+        }
+        self.listeners.insert(addr, Listener::new(addr, cfg));
+        true
+    }
+
+    // Stops listening on `addr`. Connections still waiting for accept()
+    // are closed; returns their FINs.
+    pub fn unlisten(&mut self, addr: ListenAddr, now_ms: u128) -> Outgoing {
+        let Some(mut l) = self.listeners.remove(&addr) else {
+            return vec![];
+        };
+        if let Some(w) = l.accept_waker.take() {
+            w.wake();
+        }
+        let mut out = Vec::new();
+        for h in l.accept_q {
+            out.extend(self.close(h, now_ms));
+            self.release(h);
+        }
+        out
+    }
+
+    // Next established connection on `addr`, if any.
+    pub fn accept(&mut self, addr: ListenAddr) -> Option<ConnHandle> {
+        self.listeners.get_mut(&addr)?.accept_q.pop_front()
+    }
+
+    // Drains up to buf.len() in-order bytes; re-opens the receive window.
+    pub fn recv(&mut self, h: ConnHandle, buf: &mut [u8]) -> usize {
+        let Some(c) = self.conns.get_mut(&h.0) else {
+            return 0;
+        };
+        let t = &mut c.tcb;
+        // like BSD, a read never crosses the urgent mark
+        let lim = match t.urg_mark {
+            Some(m) if m > 0 => min(m, buf.len()),
+            _ => buf.len(),
+        };
+        let n = t.app_read.read(&mut buf[..lim]);
+        t.urg_mark = match t.urg_mark {
+            Some(0) if n > 0 => None,
+            m => m.map(|m| m - n.min(m)),
+        };
+        t.update_rcv_wnd();
+        n
+    }
+
+    // Active open from local_ip on an ephemeral port; returns the handle and the SYN.
+    pub fn connect(
+        &mut self,
+        local_ip: [u8; 4],
+        remote_ip: [u8; 4],
+        remote_port: u16,
+        now_ms: u128,
+    ) -> Result<(ConnHandle, Vec<(ConnMeta, WireSeg<'static>)>), PortError> {
+        self.open(local_ip, remote_ip, remote_port, None, now_ms).map(|(h, _, out)| (h, out))
+    }
+
+    // connect() with TCP Fast Open: queues `data` (returning how much was
+    // taken) and, given a cached cookie for remote_ip, puts the first
+    // segment of it in the SYN. Without one the SYN asks for a cookie and
+    // the data follows the handshake as usual.
+    pub fn connect_fastopen(
+        &mut self,
+        local_ip: [u8; 4],
+        remote_ip: [u8; 4],
+        remote_port: u16,
+        data: &[u8],
+        now_ms: u128,
+    ) -> Result<(ConnHandle, usize, Outgoing), PortError> {
+        self.open(local_ip, remote_ip, remote_port, Some(data), now_ms)
+    }
+
+    fn open(
+        &mut self,
+        local_ip: [u8; 4],
+        remote_ip: [u8; 4],
+        remote_port: u16,
+        fastopen: Option<&[u8]>,
+        now_ms: u128,
+    ) -> Result<(ConnHandle, usize, Outgoing), PortError> {
+        let (listeners, conns) = (&self.listeners, &self.conns);
+        let port = self.ephemeral.allocate(local_ip, remote_ip, remote_port, |p| {
+            port_in_use(listeners, conns, local_ip, p, remote_ip, remote_port)
+        })?;
+        let key = FourTuple { src_ip: remote_ip, dst_ip: local_ip, src_port: remote_port, dst_port: port };
+
+        let mut tcb = Tcb::new_listen(port);
+        tcb.set_config(&self.config);
+        tcb.state = State::Closed;
+        tcb.trace = self.observer.as_ref().map(|_| Vec::new());
+        tcb.set_state(State::SynSent);
+        tcb.remote_port = remote_port;
+        tcb.local_isn = self.isn.isn(&key, now_ms);
+        tcb.iss = tcb.local_isn;
+        tcb.snd_una = tcb.iss;
+        tcb.snd_nxt = tcb.iss.wrapping_add(1);
+        tcb.syn_sent_ms = now_ms;
+        tcb.last_rx_ms = now_ms;
+        tcb.ts_ok = tcb.cfg.timestamps; // an offer until the SYN-ACK answers it
+
+        let meta = ConnMeta { key, ip_src: local_ip, ip_dst: remote_ip };
+        let mut syn = syn_seg(&tcb);
+        let mut n = 0;
+        if let Some(data) = fastopen {
+            n = tcb.sendq.write(data);
+            syn.opts.fastopen = Some(TfoCookie::request());
+            if let Some(cookie) = self.fastopen.cached(remote_ip) {
+                // the peer's MSS is not known yet, so stay within the default
+                let mut first = vec![0u8; min(n, DEFAULT_PEER_MSS as usize)];
+                tcb.sendq.peek_at(0, &mut first);
+                tcb.snd_nxt = tcb.snd_nxt.wrapping_add(first.len() as u32);
+                tcb.fastopen = !first.is_empty();
+                syn.opts.fastopen = Some(cookie);
+                syn.payload = Cow::Owned(first);
+            }
+        }
+        self.errors.remove(&key);
+        self.conns.insert(key, TcpConn::new(tcb, meta.clone(), None));
+        let mut out = vec![(meta, syn)];
+        self.snmp.inc(Ctr::TcpActiveOpens);
+        self.finish_out(&mut out, now_ms);
+        self.flush_events(key, now_ms);
+        self.rearm(key);
+        Ok((ConnHandle(key), n, out))
+    }
+
+    // Bytes send_app() would accept right now.
+    pub fn send_capacity(&self, h: ConnHandle) -> usize {
+        self.conns.get(&h.0).map_or(0, |c| c.tcb.sendq.free())
+    }
+
+    pub fn set_nodelay(&mut self, h: ConnHandle, on: bool) {
+        if let Some(c) = self.conns.get_mut(&h.0) {
+            c.tcb.nodelay = on;
+        }
+    }
+
+    // The application's close, as set up by set_linger(): shutdown(), or
+    // abort() for a zero linger time.
+    pub fn close(&mut self, h: ConnHandle, now_ms: u128) -> Outgoing {
+        let Some(c) = self.conns.get_mut(&h.0) else {
+            return vec![];
+        };
+        match c.tcb.linger {
+            Some(0) => return self.abort(h, now_ms),
+            Some(ms) if c.tcb.linger_until_ms == 0 => c.tcb.linger_until_ms = now_ms + ms as u128,
+            _ => {}
+        }
+        self.shutdown(h, now_ms)
+    }
+
+    // Active close: the FIN follows whatever is still queued, and the
+    // connection lingers in TIME-WAIT after the peer's FIN. Further sends
+    // are refused; receiving goes on until the peer closes too.
+    pub fn shutdown(&mut self, h: ConnHandle, now_ms: u128) -> Outgoing {
+        let Some(c) = self.conns.get_mut(&h.0) else {
+            return vec![];
+        };
+        if matches!(c.tcb.state, State::Closed | State::Listen | State::SynSent) {
+            // nothing to tell the peer yet
+            self.remove_conn(h.0, now_ms, None);
+            return vec![];
+        }
+        c.tcb.fin_queued = true;
+        let mut out = conn_try_send(c, now_ms);
+        self.finish_out(&mut out, now_ms);
+        self.flush_events(h.0, now_ms);
+        self.rearm(h.0);
+        out
+    }
+
+    // Abortive close: throws away both queues and resets the peer
+    // (RFC 9293 3.10.4). The connection is gone when this returns.
+    pub fn abort(&mut self, h: ConnHandle, now_ms: u128) -> Outgoing {
+        self.reset(h.0, now_ms, DropReason::Aborted)
+    }
+
+    fn reset(&mut self, key: FourTuple, now_ms: u128, reason: DropReason) -> Outgoing {
+        let Some(c) = self.conns.get(&key) else {
+            return vec![];
+        };
+        let mut out = Vec::new();
+        match c.tcb.state {
+            State::SynRcvd | State::Established | State::FinWait1 | State::FinWait2 | State::CloseWait => {
+                if matches!(c.tcb.state, State::Established | State::CloseWait) {
+                    self.snmp.inc(Ctr::TcpEstabResets);
+                }
+                let rst = WireSeg { flags: TCP_FLAG_RST | TCP_FLAG_ACK, ..ack_seg(&c.tcb) };
+                out.push((c.meta.clone(), rst));
+            }
+            // the peer is done too, or never heard from us
+            _ => {}
+        }
+        self.finish_out(&mut out, now_ms);
+        self.remove_conn(key, now_ms, Some(reason));
+        out
+    }
+
+    // SO_LINGER for close(). None, the default: close() returns at once
+    // and the connection delivers everything queued in the background, also
+    // after release(). Some(0): close() is abort(). Some(ms): a graceful
+    // close that resets the connection if our FIN is not acked within `ms`.
+    pub fn set_linger(&mut self, h: ConnHandle, linger_ms: Option<u64>) {
+        if let Some(c) = self.conns.get_mut(&h.0) {
+            c.tcb.linger = linger_ms;
+        }
+    }
+
+    // The error that ended the connection, once; None while it is alive
+    // or if it closed normally.
+    pub fn take_error(&mut self, h: ConnHandle) -> Option<TcpError> {
+        self.errors.remove(&h.0)
+    }
+
+    // The application is done with the handle: drops an unseen error and
+    // keeps whatever happens to the connection from now on from being
+    // recorded for it. Call after close().
+    pub fn release(&mut self, h: ConnHandle) {
+        self.errors.remove(&h.0);
+        if let Some(c) = self.conns.get_mut(&h.0) {
+            c.released = true;
+        }
+    }
+
+    // Overrides the stack or listener TcpConfig for one connection.
+    pub fn set_config(&mut self, h: ConnHandle, cfg: &TcpConfig) {
+        if let Some(c) = self.conns.get_mut(&h.0) {
+            c.tcb.set_config(cfg);
+        }
+    }
+
+    pub fn set_pacing(&mut self, h: ConnHandle, on: bool) {
+        if let Some(c) = self.conns.get_mut(&h.0) {
+            c.tcb.pacing = on;
+            c.tcb.pace_next_ms = 0;
+        }
+    }
+
+    pub fn state(&self, h: ConnHandle) -> Option<State> {
+        self.conns.get(&h.0).map(|c| c.tcb.state)
+    }
+
+    // Bytes recv() would return right now.
+    pub fn recv_queue(&self, h: ConnHandle) -> usize {
+        self.conns.get(&h.0).map_or(0, |c| c.tcb.app_read.len())
+    }
+
+    // Whether send_app() still takes data: not after close().
+    pub fn may_send(&self, h: ConnHandle) -> bool {
+        self.conns.get(&h.0).is_some_and(|c| {
+            !c.tcb.fin_queued && matches!(c.tcb.state, State::SynSent | State::SynRcvd | State::Established | State::CloseWait)
+        })
+    }
+
+    // Connections waiting for accept() on `addr`.
+    pub fn accept_pending(&self, addr: ListenAddr) -> usize {
+        self.listeners.get(&addr).map_or(0, |l| l.accept_q.len())
+    }
+
+    // Snapshot of a connection's state, timers and counters.
+    pub fn info(&self, h: ConnHandle) -> Option<TcpInfo> {
+        self.conns.get(&h.0).map(|c| TcpInfo::from_tcb(&c.tcb))
+    }
+
+    // The TcpConfig connections accepted on `laddr` start with.
+    pub fn listener_config(&self, laddr: ListenAddr) -> TcpConfig {
+        self.listeners.get(&laddr).and_then(|l| l.cfg.tcp).unwrap_or(self.config)
+    }
+
+    // Listener for a segment to ip:port; a specific bind wins over the wildcard.
+    fn find_listener(&self, ip: [u8; 4], port: u16) -> Option<ListenAddr> {
+        [ListenAddr::new(ip, port), ListenAddr::any(port)]
+            .into_iter()
+            .find(|a| self.listeners.contains_key(a))
+    }
+
+    pub fn syn_cookie_stats(&self) -> SynCookieStats {
+        self.syncookies.stats
+    }
+
+    pub fn fastopen_stats(&self) -> FastOpenStats {
+        self.fastopen.stats
+    }
+
+    // SYN-RECEIVED connections on `addr` whose SYN data was already accepted.
+    fn fastopen_pending(&self, addr: ListenAddr) -> usize {
+        self.conns
+            .values()
+            .filter(|c| c.tcb.state == State::SynRcvd && c.tcb.fastopen && c.listener == Some(addr))
+            .count()
+    }
+
+    fn syn_backlog_full(&self, addr: ListenAddr) -> bool {
+        self.listeners.get(&addr).is_some_and(|l| l.half_open >= self.syn_backlog)
+    }
+
+    // Whether an ACK to `addr` may be the end of a cookie handshake.
+    fn cookies_outstanding(&self, addr: ListenAddr, now_ms: u128) -> bool {
+        self.listeners
+            .get(&addr)
+            .and_then(|l| l.cookies_sent_ms)
+            .is_some_and(|at| now_ms.saturating_sub(at) <= syncookie::OVERFLOW_WINDOW_MS)
+    }
+
+    // Takes a connection off its listener's half-open count once it has
+    // left SYN-RECEIVED, or is about to be removed.
+    fn settle_half_open(&mut self, key: FourTuple, removing: bool) {
+        let Some(c) = self.conns.get_mut(&key) else {
+            return;
+        };
+        if !c.half_open || (c.tcb.state == State::SynRcvd && !removing) {
+            return;
+        }
+        c.half_open = false;
+        if let Some(l) = c.listener.and_then(|a| self.listeners.get_mut(&a)) {
+            l.half_open = l.half_open.saturating_sub(1);
+        }
+    }
+
+    // Moves a connection that just finished its handshake onto its listener's queue.
+    fn enqueue_accept(&mut self, key: FourTuple) {
+        let Some(addr) = self.conns.get(&key).and_then(|c| c.listener) else {
+            return;
+        };
+        if let Some(l) = self.listeners.get_mut(&addr) {
+            l.accept_q.push_back(ConnHandle(key));
+            if let Some(w) = l.accept_waker.take() {
+                w.wake();
+            }
+        }
+    }
+
+    // Wakers are one-shot: a future registers again each time it is pending.
+    // For a connection that no longer exists the waker fires at once.
+    pub fn register_recv_waker(&mut self, h: ConnHandle, w: &Waker) {
+        match self.conns.get_mut(&h.0) {
+            Some(c) => c.recv_waker = Some(w.clone()),
+            None => w.wake_by_ref(),
+        }
+    }
+    pub fn register_send_waker(&mut self, h: ConnHandle, w: &Waker) {
+        match self.conns.get_mut(&h.0) {
+            Some(c) => c.send_waker = Some(w.clone()),
+            None => w.wake_by_ref(),
+        }
+    }
+    pub fn register_accept_waker(&mut self, addr: ListenAddr, w: &Waker) {
+        match self.listeners.get_mut(&addr) {
+            Some(l) => l.accept_waker = Some(w.clone()),
+            None => w.wake_by_ref(),
+        }
+    }
+
+    fn wake(&mut self, key: FourTuple) {
+        if let Some(c) = self.conns.get_mut(&key) {
+            for w in [c.recv_waker.take(), c.send_waker.take()].into_iter().flatten() {
+                w.wake();
+            }
+        }
     }
 
     pub fn on_segment<'a>(
@@ -204,51 +836,497 @@ impl TcpStack {
         key: FourTuple,
         l4: &TcpSeg<'a>,
     ) -> Vec<(ConnMeta, WireSeg<'static>)> {
+        self.snmp.inc(Ctr::TcpInSegs);
+        let mut out = self.demux_segment(now_ms, key, l4);
+        self.finish_out(&mut out, now_ms);
+        self.wake(key);
+        self.reap(key, now_ms);
+        self.flush_events(key, now_ms);
+        self.rearm(key);
+        out
+    }
+
+    // Milliseconds until on_timer() has work to do; None when no timer runs.
+    pub fn poll_delay(&self, now_ms: u128) -> Option<u64> {
+        self.timers.next_deadline().map(|d| d.saturating_sub(now_ms) as u64)
+    }
+
+    // Books what a call is about to send against each connection's counters
+    // and stamps the timestamp option on connections that use it.
+    fn finish_out(&mut self, out: &mut [(ConnMeta, WireSeg<'static>)], now_ms: u128) {
+        for (meta, seg) in out {
+            self.snmp.inc(Ctr::TcpOutSegs);
+            if seg.flags & TCP_FLAG_RST != 0 {
+                self.snmp.inc(Ctr::TcpOutRsts);
+            }
+            if let Some(c) = self.conns.get_mut(&meta.key) {
+                if c.tcb.ts_ok {
+                    seg.opts.timestamps = Some((now_ms as u32, c.tcb.ts_recent));
+                }
+                c.tcb.segs_out += 1;
+                c.tcb.bytes_sent += seg.payload.len() as u64;
+                c.tcb.emit(TcpEvent::SegOut(SegSummary {
+                    seq: seg.seq,
+                    ack: seg.ack,
+                    flags: seg.flags,
+                    len: seg.payload.len(),
+                    wnd: seg.wnd,
+                }));
+            }
+        }
+    }
+
+    // Installs `obs`; every connection, existing ones included, reports to it.
+    pub fn set_observer(&mut self, obs: Box<dyn TcpObserver>) {
+        for c in self.conns.values_mut() {
+            c.tcb.trace.get_or_insert_with(Vec::new);
+        }
+        self.observer = Some(obs);
+    }
+
+    pub fn clear_observer(&mut self) {
+        for c in self.conns.values_mut() {
+            c.tcb.trace = None;
+        }
+        self.observer = None;
+    }
+
+    // Hands a connection's buffered events to the observer.
+    fn flush_events(&mut self, key: FourTuple, now_ms: u128) {
+        let Some(obs) = self.observer.as_mut() else {
+            return;
+        };
+        if let Some(events) = self.conns.get_mut(&key).and_then(|c| c.tcb.trace.as_mut()) {
+            for ev in events.drain(..) {
+                obs.on_event(now_ms, key, &ev);
+            }
+        }
+    }
+
+    // Reports an event that has no Tcb to buffer it in.
+    fn observe(&mut self, key: FourTuple, now_ms: u128, ev: TcpEvent) {
+        if let Some(obs) = self.observer.as_mut() {
+            obs.on_unmatched(now_ms, key, &ev);
+        }
+    }
+
+    // Forgets a connection, reporting why when it did not close normally.
+    // Drops connections that just finished (LAST-ACK acked) and keeps
+    // TIME-WAIT within max_time_wait.
+    fn reap(&mut self, key: FourTuple, now_ms: u128) {
+        match self.conns.get(&key).map(|c| c.tcb.state) {
+            Some(State::Closed) => self.remove_conn(key, now_ms, None),
+            Some(State::TimeWait) if self.time_wait_count() > self.max_time_wait => {
+                self.remove_conn(key, now_ms, Some(DropReason::TimeWaitOverflow));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn time_wait_count(&self) -> usize {
+        self.conns.values().filter(|c| c.tcb.state == State::TimeWait).count()
+    }
+
+    // Ends a connection on an error, which waits in `errors` for the
+    // application unless it has released the connection.
+    fn fail(&mut self, key: FourTuple, now_ms: u128, reason: DropReason, err: TcpError) {
+        if self.conns.get(&key).is_some_and(|c| !c.released) {
+            self.errors.insert(key, err);
+        }
+        self.remove_conn(key, now_ms, Some(reason));
+    }
+
+    // RFC 9293 3.10.7.4: a RST inside the receive window (in SYN-SENT, one
+    // that acks our SYN) ends the connection. TIME-WAIT ignores it, as
+    // RFC 1337 recommends.
+    fn on_rst(&mut self, now_ms: u128, key: FourTuple, seg: &TcpSeg<'_>) {
+        let Some(c) = self.conns.get(&key) else {
+            return;
+        };
+        let t = &c.tcb;
+        let acceptable = match t.state {
+            State::SynSent => seg.flags & TCP_FLAG_ACK != 0 && seg.ack == t.snd_nxt,
+            State::TimeWait | State::Closed => false,
+            _ => {
+                let wnd_end = t.rcv_nxt.wrapping_add(max(t.rcv_wnd, 1));
+                !seq_before(seg.seq, t.rcv_nxt) && seq_before(seg.seq, wnd_end)
+            }
+        };
+        if !acceptable {
+            return;
+        }
+        match t.state {
+            State::SynSent => {
+                self.snmp.inc(Ctr::TcpAttemptFails);
+                self.fail(key, now_ms, DropReason::Reset, TcpError::Refused);
+            }
+            // simultaneous open
+            State::SynRcvd if c.listener.is_none() => {
+                self.snmp.inc(Ctr::TcpAttemptFails);
+                self.fail(key, now_ms, DropReason::Reset, TcpError::Refused);
+            }
+            // a passive open nobody can have accepted yet just goes away
+            State::SynRcvd if !t.fastopen => {
+                self.snmp.inc(Ctr::TcpAttemptFails);
+                self.remove_conn(key, now_ms, Some(DropReason::Reset));
+            }
+            state => {
+                match state {
+                    State::SynRcvd => self.snmp.inc(Ctr::TcpAttemptFails),
+                    State::Established | State::CloseWait => self.snmp.inc(Ctr::TcpEstabResets),
+                    _ => {}
+                }
+                self.fail(key, now_ms, DropReason::Reset, TcpError::Reset);
+            }
+        }
+    }
+
+    // ICMP destination unreachable quoting a segment we sent on `key` with
+    // sequence number `seq`. Hard errors, and any error while our SYN is
+    // out, end the connection; soft ones are kept for if it times out.
+    pub fn on_unreachable(&mut self, now_ms: u128, key: FourTuple, seq: u32, err: TcpError, hard: bool) {
+        let Some(c) = self.conns.get_mut(&key) else {
+            return;
+        };
+        let t = &mut c.tcb;
+        // RFC 5927 4.1: only for data in flight, so a forged message has to
+        // guess the sequence space
+        if seq_before(seq, t.snd_una) || !seq_before(seq, t.snd_nxt) {
+            return;
+        }
+        if hard || t.state == State::SynSent {
+            if matches!(t.state, State::SynSent | State::SynRcvd) {
+                self.snmp.inc(Ctr::TcpAttemptFails);
+            } else if matches!(t.state, State::Established | State::CloseWait) {
+                self.snmp.inc(Ctr::TcpEstabResets);
+            }
+            self.fail(key, now_ms, DropReason::Unreachable, err);
+        } else {
+            t.soft_error = Some(err);
+        }
+    }
+
+    fn remove_conn(&mut self, key: FourTuple, now_ms: u128, reason: Option<DropReason>) {
+        self.settle_half_open(key, true);
         if let Some(c) = self.conns.get_mut(&key) {
-            return conn_on_segment(c, now_ms, l4);
+            // gone before anyone accepted it
+            if let Some(l) = c.listener.and_then(|a| self.listeners.get_mut(&a)) {
+                l.accept_q.retain(|&h| h != ConnHandle(key));
+            }
+            if let Some(reason) = reason {
+                c.tcb.emit(TcpEvent::Drop { reason, len: 0 });
+            }
+            c.tcb.set_state(State::Closed);
         }
-        if (l4.flags & TCP_FLAG_SYN) != 0 && self.listeners.contains_key(&l4.dst_port) {
+        self.flush_events(key, now_ms);
+        self.wake(key);
+        self.conns.remove(&key);
+        self.timers.cancel(key);
+    }
+
+    // Re-files a connection in the timer wheel after its Tcb changed.
+    fn rearm(&mut self, key: FourTuple) {
+        self.settle_half_open(key, false);
+        match self.conns.get(&key).and_then(|c| c.tcb.next_deadline()) {
+            Some(d) => self.timers.schedule(key, d),
+            None => self.timers.cancel(key),
+        }
+    }
+
+    fn demux_segment(
+        &mut self,
+        now_ms: u128,
+        key: FourTuple,
+        l4: &TcpSeg<'_>,
+    ) -> Vec<(ConnMeta, WireSeg<'static>)> {
+        let syn = (l4.flags & TCP_FLAG_SYN) != 0;
+        let ack = (l4.flags & TCP_FLAG_ACK) != 0;
+        let rst = (l4.flags & TCP_FLAG_RST) != 0;
+        // a new SYN for a tuple in TIME-WAIT
+        let mut reuse_isn = None;
+        if let Some(c) = self.conns.get(&key)
+            && c.tcb.state == State::TimeWait
+            && syn
+            && !ack
+            && !rst
+        {
+            if !time_wait_reusable(&c.tcb, l4) {
+                return vec![];
+            }
+            reuse_isn = Some(c.tcb.snd_nxt.wrapping_add(REUSE_ISN_GAP));
+            self.remove_conn(key, now_ms, None);
+        }
+        if rst && self.conns.contains_key(&key) {
+            self.on_rst(now_ms, key, l4);
+            return vec![];
+        }
+        if let Some(c) = self.conns.get_mut(&key) {
+            let was = c.tcb.state;
+            if was == State::SynRcvd
+                && !c.tcb.fastopen
+                && let Some(l) = c.listener.and_then(|a| self.listeners.get(&a))
+                && l.accept_q_full()
+            {
+                // no room to hand the connection over; the peer will retransmit
+                c.tcb.emit(TcpEvent::Drop { reason: DropReason::AcceptQueueFull, len: l4.payload.len() });
+                return vec![];
+            }
+            let out = conn_on_segment(c, now_ms, l4, &self.snmp);
+            let (now, early) = (c.tcb.state, c.tcb.fastopen);
+            let syn_data_acked = !early || c.tcb.snd_una != c.tcb.iss.wrapping_add(1);
+            // Fast Open connections were queued when their SYN arrived
+            if was == State::SynRcvd && now == State::Established && !early {
+                self.enqueue_accept(key);
+            }
+            if was == State::SynSent && now == State::Established {
+                match l4.opts.fastopen {
+                    Some(cookie) if !cookie.is_request() => self.fastopen.remember(key.src_ip, cookie),
+                    _ if !syn_data_acked => self.fastopen.forget(key.src_ip),
+                    _ => {}
+                }
+                if !syn_data_acked {
+                    self.fastopen.stats.fallbacks += 1;
+                }
+            }
+            return out;
+        }
+        let Some(laddr) = self.find_listener(key.dst_ip, l4.dst_port) else {
+            let ev = TcpEvent::Drop { reason: DropReason::NoListener, len: l4.payload.len() };
+            self.observe(key, now_ms, ev);
+            return reset_for(key, l4).into_iter().collect();
+        };
+        if syn && !ack && !rst && self.syn_backlog_full(laddr) {
+            // stateless SYN-ACK
+            let peer_mss = l4.opts.mss.unwrap_or(DEFAULT_PEER_MSS);
+            let (isn, _) = self.syncookies.make(&key, l4.seq, peer_mss, now_ms);
+            if let Some(l) = self.listeners.get_mut(&laddr) {
+                l.cookies_sent_ms = Some(now_ms);
+            }
+            let meta = ConnMeta { key, ip_src: key.dst_ip, ip_dst: key.src_ip };
+            let synack = WireSeg {
+                src_port: l4.dst_port,
+                dst_port: l4.src_port,
+                seq: isn,
+                ack: l4.seq.wrapping_add(1),
+                flags: TCP_FLAG_SYN | TCP_FLAG_ACK,
+                wnd: 65535,
+                payload: Cow::Borrowed(&[]),
+                opts: TcpOptions { mss: Some(self.listener_config(laddr).mss as u16), ..TcpOptions::default() },
+                urg_ptr: 0,
+            };
+            return vec![(meta, synack)];
+        }
+        if ack && !syn && !rst {
+            if self.cookies_outstanding(laddr, now_ms) {
+                return self.on_cookie_ack(now_ms, key, laddr, l4);
+            }
+            // RFC 9293 3.10.7.2: an ACK in LISTEN gets a reset
+            return reset_for(key, l4).into_iter().collect();
+        }
+        if syn {
             let mut tcb = Tcb::new_listen(l4.dst_port);
-            tcb.state = State::SynRcvd;
+            tcb.local_isn = reuse_isn.unwrap_or_else(|| self.isn.isn(&key, now_ms));
+            tcb.configure(&self.listeners[&laddr].cfg);
+            tcb.set_config(&self.listener_config(laddr));
+            tcb.last_rx_ms = now_ms;
+            tcb.segs_in = 1; // the SYN
+            tcb.trace = self.observer.as_ref().map(|_| Vec::new());
+            tcb.emit(TcpEvent::SegIn(summary(l4)));
+            tcb.set_state(State::SynRcvd);
+            tcb.syn_sent_ms = now_ms;
             tcb.remote_port = l4.src_port;
             tcb.irs = l4.seq;
             tcb.rcv_nxt = l4.seq.wrapping_add(1);
             tcb.iss = tcb.local_isn;
             tcb.snd_una = tcb.iss;
             tcb.snd_nxt = tcb.iss.wrapping_add(1);
+            tcb.set_peer_mss(l4.opts.mss);
+            if let Some((val, _)) = l4.opts.timestamps.filter(|_| tcb.cfg.timestamps) {
+                tcb.ts_ok = true;
+                tcb.ts_recent = val;
+            }
+
+            // Fast Open: take the SYN's data on a valid cookie, otherwise
+            // ack only the SYN and hand out a cookie for next time
+            let mut cookie = None;
+            let cfg = self.listeners[&laddr].cfg;
+            if cfg.fastopen_queue > 0
+                && let Some(c) = l4.opts.fastopen
+            {
+                if self.fastopen.check(key.src_ip, &c)
+                    && self.fastopen_pending(laddr) < cfg.fastopen_queue
+                    && !self.listeners[&laddr].accept_q_full()
+                {
+                    let n = tcb.app_read.write(l4.payload);
+                    tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(n as u32);
+                    tcb.update_rcv_wnd();
+                    tcb.fastopen = true;
+                    self.fastopen.stats.accepted += 1;
+                } else {
+                    if !c.is_request() {
+                        self.fastopen.stats.rejected += 1;
+                    }
+                    cookie = Some(self.fastopen.cookie(key.src_ip));
+                    self.fastopen.stats.cookies_sent += 1;
+                }
+            }
 
             let meta = ConnMeta { key, ip_src: key.dst_ip, ip_dst: key.src_ip };
-            let mut conn = TcpConn { tcb, meta: meta.clone() };
-            let synack = WireSeg {
-                src_port: conn.tcb.local_port,
-                dst_port: conn.tcb.remote_port,
-                seq: conn.tcb.iss,
-                ack: conn.tcb.rcv_nxt,
-                flags: TCP_FLAG_SYN | TCP_FLAG_ACK,
-                wnd: conn.tcb.rcv_wnd as u16,
-                payload: &[],
-            };
+            let mut conn = TcpConn::new(tcb, meta.clone(), Some(laddr));
+            conn.half_open = true;
+            let mut synack = synack_seg(&conn.tcb);
+            synack.opts.fastopen = cookie;
+            let early = conn.tcb.fastopen;
+            self.errors.remove(&key);
             self.conns.insert(key, conn);
+            if let Some(l) = self.listeners.get_mut(&laddr) {
+                l.half_open += 1;
+            }
+            self.snmp.inc(Ctr::TcpPassiveOpens);
+            if early {
+                self.enqueue_accept(key);
+            }
             return vec![(meta, synack)];
         }
         vec![]
     }
 
+    // Final ACK of a handshake we answered with a SYN cookie: rebuild the
+    // Tcb. Anything else gets the reset an ACK in LISTEN would.
+    fn on_cookie_ack(
+        &mut self,
+        now_ms: u128,
+        key: FourTuple,
+        laddr: ListenAddr,
+        l4: &TcpSeg<'_>,
+    ) -> Vec<(ConnMeta, WireSeg<'static>)> {
+        let Some(mss) = self.syncookies.check(&key, l4.seq, l4.ack, now_ms) else {
+            let ev = TcpEvent::Drop { reason: DropReason::BadSynCookie, len: l4.payload.len() };
+            self.observe(key, now_ms, ev);
+            return reset_for(key, l4).into_iter().collect();
+        };
+        let l = &self.listeners[&laddr];
+        if l.accept_q_full() {
+            return vec![]; // the peer's next segment will try again
+        }
+        let cfg = l.cfg;
+        let mut tcb = Tcb::new_listen(l4.dst_port);
+        tcb.configure(&cfg);
+        tcb.set_config(&self.listener_config(laddr));
+        tcb.last_rx_ms = now_ms;
+        tcb.trace = self.observer.as_ref().map(|_| Vec::new());
+        tcb.set_state(State::Established);
+        tcb.remote_port = l4.src_port;
+        tcb.irs = l4.seq.wrapping_sub(1);
+        tcb.rcv_nxt = l4.seq;
+        tcb.iss = l4.ack.wrapping_sub(1);
+        tcb.snd_una = l4.ack;
+        tcb.snd_nxt = l4.ack;
+        tcb.set_peer_mss(Some(mss));
+
+        let meta = ConnMeta { key, ip_src: key.dst_ip, ip_dst: key.src_ip };
+        self.snmp.inc(Ctr::TcpPassiveOpens);
+        self.errors.remove(&key);
+        let c = self.conns.entry(key).or_insert(TcpConn::new(tcb, meta, Some(laddr)));
+        // the ACK may already carry data
+        let out = conn_on_segment(c, now_ms, l4, &self.snmp);
+        self.enqueue_accept(key);
+        out
+    }
+
+    // Runs the timers that have come due; only expired connections are touched.
     pub fn on_timer(&mut self, now_ms: u128) -> Vec<(ConnMeta, WireSeg<'static>)> {
         let mut out = Vec::new();
-        let keys: Vec<_> = self.conns.keys().cloned().collect();
-        for k in keys {
+        for k in self.timers.expire(now_ms) {
+            let start = out.len();
             if let Some(c) = self.conns.get_mut(&k) {
-                // TIME-WAIT cleanup
-                if c.tcb.state == State::TimeWait && now_ms >= c.tcb.timewait_until_ms {
-                    self.conns.remove(&k);
+                // TIME-WAIT over, or FIN-WAIT-2 gave up on the peer's FIN
+                let waiting = c.tcb.state == State::TimeWait
+                    || c.tcb.state == State::FinWait2 && c.tcb.timewait_until_ms != 0;
+                if waiting && now_ms >= c.tcb.timewait_until_ms {
+                    self.remove_conn(k, now_ms, None);
                     continue;
                 }
-                // delayed ACK
-                if c.tcb.ack_due_ms != 0
-                    && now_ms >= c.tcb.ack_due_ms
+                // linger ran out before the peer acked our FIN
+                if c.tcb.linger_until_ms != 0 && now_ms >= c.tcb.linger_until_ms && !c.tcb.fin_acked() {
+                    if !c.released {
+                        self.errors.insert(k, TcpError::TimedOut);
+                    }
+                    out.extend(self.reset(k, now_ms, DropReason::LingerTimeout));
+                    continue;
+                }
+                // SYN retransmission
+                if c.tcb.state == State::SynSent
+                    && now_ms.saturating_sub(c.tcb.syn_sent_ms) >= c.tcb.rto.rto_ms as u128
+                {
+                    if c.tcb.retries >= c.tcb.cfg.syn_retries {
+                        self.snmp.inc(Ctr::TcpAttemptFails);
+                        let err = c.tcb.soft_error.unwrap_or(TcpError::TimedOut);
+                        self.fail(k, now_ms, DropReason::SynTimeout, err);
+                        continue;
+                    }
+                    c.tcb.retries += 1;
+                    c.tcb.rto.backoff();
+                    c.tcb.syn_sent_ms = now_ms;
+                    // retry without Fast Open data: it may be what got the SYN dropped
+                    c.tcb.snd_nxt = c.tcb.iss.wrapping_add(1);
+                    self.snmp.inc(Ctr::TcpRetransSegs);
+                    c.tcb.emit(TcpEvent::Retransmit { kind: RetransmitKind::Syn, seq: c.tcb.iss, len: 0 });
+                    out.push((c.meta.clone(), syn_seg(&c.tcb)));
+                }
+                // SYN-ACK retransmission; a passive open nobody has
+                // accepted yet just goes away, freeing its backlog slot
+                if c.tcb.state == State::SynRcvd
+                    && now_ms.saturating_sub(c.tcb.syn_sent_ms) >= c.tcb.rto.rto_ms as u128
+                {
+                    if c.tcb.retries >= c.tcb.cfg.synack_retries {
+                        self.snmp.inc(Ctr::TcpAttemptFails);
+                        if c.listener.is_some() && !c.tcb.fastopen {
+                            self.remove_conn(k, now_ms, Some(DropReason::SynTimeout));
+                        } else {
+                            let err = c.tcb.soft_error.unwrap_or(TcpError::TimedOut);
+                            self.fail(k, now_ms, DropReason::SynTimeout, err);
+                        }
+                        continue;
+                    }
+                    c.tcb.retries += 1;
+                    c.tcb.rto.backoff();
+                    c.tcb.syn_sent_ms = now_ms;
+                    self.snmp.inc(Ctr::TcpRetransSegs);
+                    c.tcb.emit(TcpEvent::Retransmit { kind: RetransmitKind::Syn, seq: c.tcb.iss, len: 0 });
+                    out.push((c.meta.clone(), synack_seg(&c.tcb)));
+                }
+                // keepalive: probe an idle peer, give up after ka.probes misses
+                if let Some(ka) = c.tcb.keepalive
                     && c.tcb.state == State::Established
                 {
+                    let due = c.tcb.last_rx_ms
+                        + ka.idle_ms as u128
+                        + c.tcb.ka_probes as u128 * ka.interval_ms as u128;
+                    if now_ms >= due {
+                        if c.tcb.ka_probes >= ka.probes {
+                            self.snmp.inc(Ctr::TcpEstabResets);
+                            let err = c.tcb.soft_error.unwrap_or(TcpError::TimedOut);
+                            self.fail(k, now_ms, DropReason::KeepaliveTimeout, err);
+                            continue;
+                        }
+                        c.tcb.ka_probes += 1;
+                        let seg = WireSeg {
+                            src_port: c.tcb.local_port,
+                            dst_port: c.tcb.remote_port,
+                            seq: c.tcb.snd_una.wrapping_sub(1),
+                            ack: c.tcb.rcv_nxt,
+                            flags: TCP_FLAG_ACK,
+                            wnd: c.tcb.rcv_wnd as u16,
+                            payload: Cow::Borrowed(&[]),
+                            opts: TcpOptions::default(),
+                            urg_ptr: 0,
+                        };
+                        out.push((c.meta.clone(), seg));
+                    }
+                }
+                // delayed ACK
+                if c.tcb.ack_due_ms != 0 && now_ms >= c.tcb.ack_due_ms {
                     let seg = WireSeg {
                         src_port: c.tcb.local_port,
                         dst_port: c.tcb.remote_port,
@@ -256,44 +1334,70 @@ impl TcpStack {
                         ack: c.tcb.rcv_nxt,
                         flags: TCP_FLAG_ACK,
                         wnd: c.tcb.rcv_wnd as u16,
-                        payload: &[],
+                        payload: Cow::Borrowed(&[]),
+                        opts: TcpOptions::default(),
+                        urg_ptr: 0,
                     };
                     c.tcb.ack_due_ms = 0;
                     out.push((c.meta.clone(), seg));
                 }
-                // RTO on oldest unacked
-                if let Some((&seq, &(len, sent_at))) = c.tcb.unacked.iter().next() {
-                    let rto_u128 = c.tcb.rto.rto_ms as u128;
-                    if now_ms.saturating_sub(sent_at) >= rto_u128 {
-                        // placeholder payload of correct length
-                        let payload_box = vec![0u8; len].into_boxed_slice();
-                        let payload: &'static [u8] = Box::leak(payload_box);
+                // RTO: resend the earliest unacked segment (RFC 6298 5.4-5.6)
+                if c.tcb.rto_due_ms != 0 && now_ms >= c.tcb.rto_due_ms {
+                    if c.tcb.retries >= c.tcb.cfg.data_retries {
+                        self.snmp.inc(Ctr::TcpEstabResets);
+                        out.truncate(start);
+                        let err = c.tcb.soft_error.unwrap_or(TcpError::TimedOut);
+                        self.fail(k, now_ms, DropReason::RetransmitTimeout, err);
+                        continue;
+                    }
+                    c.tcb.retries += 1;
+                    c.tcb.rto_due_ms = 0;
+                    if let Some((&seq, s)) = c.tcb.unacked.iter_mut().next() {
+                        s.retransmitted = true;
+                        let (len, fin) = (s.len, if s.fin { TCP_FLAG_FIN } else { 0 });
+                        c.tcb.bytes_retrans += len as u64;
+                        self.snmp.inc(Ctr::TcpRetransSegs);
+                        c.tcb.emit(TcpEvent::Retransmit { kind: RetransmitKind::Rto, seq, len });
+                        let (urg, urg_ptr) = urgent_fields(&c.tcb, seq);
                         let seg = WireSeg {
                             src_port: c.tcb.local_port,
                             dst_port: c.tcb.remote_port,
                             seq,
                             ack: c.tcb.rcv_nxt,
-                            flags: TCP_FLAG_ACK,
+                            flags: TCP_FLAG_ACK | urg | fin,
                             wnd: c.tcb.rcv_wnd as u16,
-                            payload,
+                            payload: Cow::Owned(unacked_payload(&c.tcb, seq, len)),
+                            opts: TcpOptions::default(),
+                            urg_ptr,
                         };
                         c.tcb.rto.backoff();
-                        c.tcb.ssthresh = max(c.tcb.cwnd / 2, 2 * MAX_SEG);
-                        c.tcb.cwnd = MAX_SEG;
+                        c.tcb.rto_due_ms = now_ms + c.tcb.rto.rto_ms as u128;
+                        if c.tcb.cc == CongestionControl::Reno {
+                            c.tcb.ssthresh = max(c.tcb.cwnd / 2, 2 * c.tcb.mss);
+                            c.tcb.cwnd = c.tcb.mss;
+                            c.tcb.emit_cwnd();
+                        }
                         out.push((c.meta.clone(), seg));
                     }
                 }
                 // zero-window probe
-                if c.tcb.snd_wnd == 0 && !c.tcb.sendq.is_empty() && c.tcb.state == State::Established
-                {
+                if c.tcb.persist_due_ms != 0 && now_ms >= c.tcb.persist_due_ms {
+                    c.tcb.persist_backoff += 1;
+                    let backoff = (c.tcb.rto.rto_ms as u128) << c.tcb.persist_backoff.min(16);
+                    c.tcb.persist_due_ms = now_ms + backoff.min(PERSIST_MAX_MS);
+                    // also how urgent data is announced through a closed window
+                    let seq = c.tcb.snd_nxt.wrapping_sub(1);
+                    let (urg, urg_ptr) = urgent_fields(&c.tcb, seq);
                     let seg = WireSeg {
                         src_port: c.tcb.local_port,
                         dst_port: c.tcb.remote_port,
-                        seq: c.tcb.snd_nxt.wrapping_sub(1),
+                        seq,
                         ack: c.tcb.rcv_nxt,
-                        flags: TCP_FLAG_ACK,
+                        flags: TCP_FLAG_ACK | urg,
                         wnd: c.tcb.rcv_wnd as u16,
-                        payload: &[],
+                        payload: Cow::Borrowed(&[]),
+                        opts: TcpOptions::default(),
+                        urg_ptr,
                     };
                     out.push((c.meta.clone(), seg));
                 }
@@ -301,23 +1405,87 @@ impl TcpStack {
                 let mut more = conn_try_send(c, now_ms);
                 out.append(&mut more);
             }
+            self.finish_out(&mut out[start..], now_ms);
+            self.wake(k);
+            self.flush_events(k, now_ms);
+            self.rearm(k);
         }
         out
     }
 
+    // Queues as much of `data` as the send buffer has room for; returns the
+    // number of bytes taken plus any segments that can go out right away.
     pub fn send_app(
         &mut self,
-        key: &FourTuple,
+        h: ConnHandle,
         data: &[u8],
         now_ms: u128,
-    ) -> Vec<(ConnMeta, WireSeg<'static>)> {
-        if let Some(c) = self.conns.get_mut(key) {
-            for b in data {
-                c.tcb.sendq.push_back(*b);
-            }
-            return conn_try_send(c, now_ms);
+    ) -> (usize, Vec<(ConnMeta, WireSeg<'static>)>) {
+        let Some(c) = self.conns.get_mut(&h.0) else {
+            return (0, vec![]);
+        };
+        if c.tcb.fin_queued {
+            return (0, vec![]);
+        }
+        let n = c.tcb.sendq.write(data);
+        let mut out = conn_try_send(c, now_ms);
+        self.finish_out(&mut out, now_ms);
+        self.flush_events(h.0, now_ms);
+        self.rearm(h.0);
+        (n, out)
+    }
+
+    // send_app, with the end of what was queued becoming the urgent point.
+    // Segments covering data before it go out with URG set, so the peer
+    // learns of it even while its window is closed.
+    pub fn send_urgent(
+        &mut self,
+        h: ConnHandle,
+        data: &[u8],
+        now_ms: u128,
+    ) -> (usize, Vec<(ConnMeta, WireSeg<'static>)>) {
+        let Some(c) = self.conns.get_mut(&h.0) else {
+            return (0, vec![]);
+        };
+        let t = &mut c.tcb;
+        if t.fin_queued {
+            return (0, vec![]);
+        }
+        let n = t.sendq.write(data);
+        if n > 0 {
+            // sendq starts at snd_una, which is still our SYN during the handshake
+            let syn = matches!(t.state, State::SynSent | State::SynRcvd) as u32;
+            t.snd_up = Some(t.snd_una.wrapping_add(syn + t.sendq.len() as u32));
+        }
+        let mut out = conn_try_send(c, now_ms);
+        self.finish_out(&mut out, now_ms);
+        self.flush_events(h.0, now_ms);
+        self.rearm(h.0);
+        (n, out)
+    }
+
+    // The out-of-band byte (the last urgent byte), unless the connection
+    // receives urgent data inline.
+    pub fn recv_oob(&mut self, h: ConnHandle) -> Option<u8> {
+        self.conns.get_mut(&h.0)?.tcb.oob.take()
+    }
+
+    // RFC 6093 recommends inline delivery: the urgent byte stays in the
+    // stream and only the mark tells the application where urgent data ends.
+    pub fn set_oob_inline(&mut self, h: ConnHandle, on: bool) {
+        if let Some(c) = self.conns.get_mut(&h.0) {
+            c.tcb.oob_inline = on;
         }
-        vec![]
+    }
+
+    // The next recv() starts at the urgent mark.
+    pub fn at_urgent_mark(&self, h: ConnHandle) -> bool {
+        self.conns.get(&h.0).is_some_and(|c| c.tcb.urg_mark == Some(0))
+    }
+
+    // The peer has signalled urgent data the application has not read up to.
+    pub fn urgent_pending(&self, h: ConnHandle) -> bool {
+        self.conns.get(&h.0).is_some_and(|c| c.tcb.rcv_up.is_some() || c.tcb.urg_mark.is_some())
     }
 }
 
@@ -327,102 +1495,222 @@ fn conn_on_segment<'a>(
     c: &mut TcpConn,
     now_ms: u128,
     seg: &TcpSeg<'a>,
+    snmp: &Snmp,
 ) -> Vec<(ConnMeta, WireSeg<'static>)> {
     let t = &mut c.tcb;
     let mut out = Vec::new();
 
+    t.last_rx_ms = now_ms;
+    t.ka_probes = 0;
+    t.segs_in += 1;
+    t.emit(TcpEvent::SegIn(summary(seg)));
+    let cc_before = (t.cwnd, t.ssthresh);
+
+    // RFC 9293 3.10.7.4: an ACK of something not yet sent is dropped before
+    // it touches the send side; a RST while our SYN is unacked, else an ACK
+    if (seg.flags & TCP_FLAG_ACK) != 0 && seq_before(t.snd_nxt, seg.ack) {
+        let reply = match t.state {
+            State::SynSent | State::SynRcvd => WireSeg {
+                seq: seg.ack,
+                ack: 0,
+                flags: TCP_FLAG_RST,
+                wnd: 0,
+                ..ack_seg(t)
+            },
+            _ => ack_seg(t),
+        };
+        out.push((c.meta.clone(), reply));
+        return out;
+    }
+
+    // RFC 7323 4.3: remember TSval from segments that are not from the future
+    if t.ts_ok
+        && let Some((val, _)) = seg.opts.timestamps
+        && !seq_before(t.rcv_nxt, seg.seq)
+    {
+        t.ts_recent = val;
+    }
+
     // peer window
     t.snd_wnd = seg.wnd as u32;
+    if t.snd_wnd > 0 {
+        t.persist_due_ms = 0;
+        t.persist_backoff = 0;
+    }
 
     // ACK processing (advance snd_una, RTT sample)
     if (seg.flags & TCP_FLAG_ACK) != 0 {
         if seg.ack.wrapping_sub(t.snd_una) as i32 > 0 {
-            // RTT sample using head unacked
-            if let Some((&first_seq, &(len, sent_at))) = t.unacked.iter().next() {
-                if seg.ack.wrapping_sub(first_seq) as i32 >= len as i32 {
-                    let rtt = now_ms.saturating_sub(sent_at) as u64;
-                    if rtt > 0 {
-                        t.rto.sample(rtt);
-                    }
-                }
-            }
-            // remove acked
+            // remove acked, splitting a partially acked head so the map
+            // always starts at the new snd_una
             let keys: Vec<u32> = t.unacked.keys().cloned().collect();
             let mut newly = 0usize;
+            let mut rtt = None;
             for k in keys {
-                let (len, _) = *t.unacked.get(&k).unwrap();
-                if seg.ack.wrapping_sub(k) as i32 >= len as i32 {
-                    t.unacked.remove(&k);
-                    newly += len;
+                let s = t.unacked.remove(&k).unwrap();
+                let covered = seg.ack.wrapping_sub(k) as i32;
+                if covered >= s.len as i32 + s.fin as i32 {
+                    newly += s.len;
+                    // Karn: an ambiguous ACK of a retransmission is no sample
+                    if !s.retransmitted {
+                        rtt = Some(now_ms.saturating_sub(s.sent_ms) as u64);
+                    }
+                } else if covered > 0 {
+                    newly += covered as usize;
+                    t.unacked.insert(seg.ack, SentSeg { len: s.len - covered as usize, ..s });
+                } else {
+                    t.unacked.insert(k, s);
                 }
             }
+            if let Some(rtt) = rtt {
+                t.rto.sample(rtt);
+                t.emit(TcpEvent::RttSample {
+                    rtt_ms: rtt,
+                    srtt_ms: t.rto.srtt_ms().unwrap_or(0),
+                    rttvar_ms: t.rto.rttvar_ms().unwrap_or(0),
+                    rto_ms: t.rto.rto_ms,
+                });
+            }
+            // 5.2 / 5.3: stop the timer once everything is acked, else restart it
+            t.rto_due_ms = if t.unacked.is_empty() { 0 } else { now_ms + t.rto.rto_ms as u128 };
+            // the SYN takes a sequence number but no queue space
+            let mut acked = seg.ack.wrapping_sub(t.snd_una) as usize;
+            if matches!(t.state, State::SynSent | State::SynRcvd) {
+                acked -= 1;
+            }
+            // and so does the FIN
+            if t.fin_seq.is_some_and(|f| seq_before(f, seg.ack)) {
+                acked -= 1;
+            }
+            t.sendq.consume(acked);
+            t.bytes_acked += acked as u64;
             t.snd_una = seg.ack;
+            t.retries = 0;
+            t.soft_error = None;
+            if t.snd_up.is_some_and(|up| !seq_before(t.snd_una, up)) {
+                t.snd_up = None;
+            }
             t.flight = t.flight.saturating_sub(newly);
 
             // CC growth
-            if newly > 0 {
+            if newly > 0 && t.cc == CongestionControl::Reno {
                 if t.cwnd < t.ssthresh {
                     t.cwnd += newly;
                 } else {
-                    t.cwnd += (MAX_SEG * newly) / t.cwnd.max(1);
+                    t.cwnd += (t.mss * newly) / t.cwnd.max(1);
                 }
             }
             t.dupacks = 0;
         } else if seg.ack == t.snd_una && !t.unacked.is_empty() && seg.payload.is_empty() {
             // DUP-ACK
             t.dupacks += 1;
-            if t.dupacks >= DUPACK_THRESHOLD {
-                if let Some((&seq, &(len, _))) = t.unacked.iter().next() {
-                    let payload_box = vec![0u8; len].into_boxed_slice();
-                    let payload: &'static [u8] = Box::leak(payload_box);
-                    let rs = WireSeg {
-                        src_port: t.local_port,
-                        dst_port: t.remote_port,
-                        seq,
-                        ack: t.rcv_nxt,
-                        flags: TCP_FLAG_ACK,
-                        wnd: t.rcv_wnd as u16,
-                        payload,
-                    };
-                    t.ssthresh = max(t.cwnd / 2, 2 * MAX_SEG);
-                    t.cwnd = t.ssthresh + 3 * MAX_SEG;
-                    out.push((c.meta.clone(), rs));
+            t.dupacks_in += 1;
+            if t.dupacks == t.cfg.dupack_threshold
+                && let Some((&seq, s)) = t.unacked.iter_mut().next()
+            {
+                s.retransmitted = true;
+                let (len, fin) = (s.len, if s.fin { TCP_FLAG_FIN } else { 0 });
+                t.bytes_retrans += len as u64;
+                snmp.inc(Ctr::TcpRetransSegs);
+                t.emit(TcpEvent::Retransmit { kind: RetransmitKind::Fast, seq, len });
+                let (urg, urg_ptr) = urgent_fields(t, seq);
+                let rs = WireSeg {
+                    src_port: t.local_port,
+                    dst_port: t.remote_port,
+                    seq,
+                    ack: t.rcv_nxt,
+                    flags: TCP_FLAG_ACK | urg | fin,
+                    wnd: t.rcv_wnd as u16,
+                    payload: Cow::Owned(unacked_payload(t, seq, len)),
+                    opts: TcpOptions::default(),
+                    urg_ptr,
+                };
+                if t.cc == CongestionControl::Reno {
+                    t.ssthresh = max(t.cwnd / 2, 2 * t.mss);
+                    t.cwnd = t.ssthresh + t.cfg.dupack_threshold as usize * t.mss;
                 }
+                out.push((c.meta.clone(), rs));
             }
         }
     }
+    if (t.cwnd, t.ssthresh) != cc_before {
+        t.emit_cwnd();
+    }
 
     match t.state {
-        State::SynRcvd => {
-            if (seg.flags & TCP_FLAG_ACK) != 0 && seg.ack == t.snd_nxt {
-                t.snd_una = seg.ack;
-                t.state = State::Established;
-            }
+        State::SynSent
+            if (seg.flags & TCP_FLAG_SYN) != 0
+                && (seg.flags & TCP_FLAG_ACK) != 0
+                && seq_before(t.iss, seg.ack)
+                && !seq_before(t.snd_nxt, seg.ack) =>
+        {
+            t.irs = seg.seq;
+            t.rcv_nxt = seg.seq.wrapping_add(1);
+            t.snd_una = seg.ack;
+            // SYN data the server did not take goes out again as normal data
+            t.snd_nxt = seg.ack;
+            t.set_peer_mss(seg.opts.mss);
+            // we offered timestamps; they stay on only if the peer agreed
+            t.ts_ok &= seg.opts.timestamps.is_some();
+            t.ts_recent = seg.opts.timestamps.map_or(0, |(val, _)| val);
+            t.set_state(State::Established);
+            out.push((
+                c.meta.clone(),
+                WireSeg {
+                    src_port: t.local_port,
+                    dst_port: t.remote_port,
+                    seq: t.snd_nxt,
+                    ack: t.rcv_nxt,
+                    flags: TCP_FLAG_ACK,
+                    wnd: t.rcv_wnd as u16,
+                    payload: Cow::Borrowed(&[]),
+                    opts: TcpOptions::default(),
+                    urg_ptr: 0,
+                },
+            ));
         }
-        State::Established => {
+        State::SynRcvd if (seg.flags & TCP_FLAG_ACK) != 0 && seg.ack == t.snd_nxt => {
+            t.snd_una = seg.ack;
+            t.set_state(State::Established);
+        }
+        // still receiving: after close() too, until the peer's FIN
+        State::Established | State::FinWait1 | State::FinWait2 => {
+            // urgent pointer: keep the furthest one still ahead of the stream
+            if (seg.flags & TCP_FLAG_URG) != 0 && seg.urg_ptr != 0 {
+                let up = seg.seq.wrapping_add(seg.urg_ptr as u32);
+                if seq_before(t.rcv_nxt, up) && t.rcv_up.is_none_or(|cur| seq_before(cur, up)) {
+                    t.rcv_up = Some(up);
+                }
+            }
             // inbound data
             if !seg.payload.is_empty() {
-                if seg.seq == t.rcv_nxt {
-                    t.rcv_nxt = t.rcv_nxt.wrapping_add(seg.payload.len() as u32);
-                    t.app_read.extend(seg.payload);
+                let seg_end = seg.seq.wrapping_add(seg.payload.len() as u32);
+                if !seq_before(t.rcv_nxt, seg.seq) && seq_before(t.rcv_nxt, seg_end) {
+                    // in order, possibly overlapping bytes we already have
+                    let fresh = &seg.payload[t.rcv_nxt.wrapping_sub(seg.seq) as usize..];
+                    let n = deliver(t, fresh);
+                    t.rcv_nxt = t.rcv_nxt.wrapping_add(n as u32);
+                    if n < fresh.len() {
+                        let len = fresh.len() - n;
+                        t.emit(TcpEvent::Drop { reason: DropReason::ReceiveBufferFull, len });
+                    }
 
                     // pull ooo
-                    loop {
-                        if let Some((&seq, data)) = t.ooo.iter().next() {
-                            if seq == t.rcv_nxt {
-                                t.rcv_nxt = t.rcv_nxt.wrapping_add(data.len() as u32);
-                                for b in data {
-                                    t.app_read.push_back(*b);
-                                }
-                                t.ooo.remove(&seq);
-                                continue;
-                            }
+                    while let Some(data) = t.ooo.pop_ready(t.rcv_nxt) {
+                        let n = deliver(t, &data);
+                        t.rcv_nxt = t.rcv_nxt.wrapping_add(n as u32);
+                        if n < data.len() {
+                            // app_read is full; keep the rest for later
+                            t.ooo.insert(t.rcv_nxt, t.rcv_nxt, &data[n..]);
+                            break;
                         }
-                        break;
                     }
-                    t.ack_due_ms = now_ms + DELAYED_ACK_MS as u128;
+                    t.update_rcv_wnd();
+                    t.ack_due_ms = now_ms + t.cfg.delayed_ack_ms as u128;
                 } else if seq_before(seg.seq, t.rcv_nxt) {
                     // old -> immediate ACK
+                    let len = seg.payload.len();
+                    t.emit(TcpEvent::Drop { reason: DropReason::Duplicate, len });
                     out.push((
                         c.meta.clone(),
                         WireSeg {
@@ -432,12 +1720,19 @@ fn conn_on_segment<'a>(
                             ack: t.rcv_nxt,
                             flags: TCP_FLAG_ACK,
                             wnd: t.rcv_wnd as u16,
-                            payload: &[],
+                            payload: Cow::Borrowed(&[]),
+                            opts: TcpOptions::default(),
+                            urg_ptr: 0,
                         },
                     ));
                 } else {
                     // future -> store & dup-ack
-                    t.ooo.insert(seg.seq, seg.payload.to_vec());
+                    t.ooo.insert(t.rcv_nxt, seg.seq, seg.payload);
+                    let past_cap = (seg_end.wrapping_sub(t.rcv_nxt) as usize).saturating_sub(t.ooo.cap);
+                    if past_cap > 0 {
+                        let len = past_cap.min(seg.payload.len());
+                        t.emit(TcpEvent::Drop { reason: DropReason::ReassemblyFull, len });
+                    }
                     out.push((
                         c.meta.clone(),
                         WireSeg {
@@ -447,46 +1742,41 @@ fn conn_on_segment<'a>(
                             ack: t.rcv_nxt,
                             flags: TCP_FLAG_ACK,
                             wnd: t.rcv_wnd as u16,
-                            payload: &[],
+                            payload: Cow::Borrowed(&[]),
+                            opts: TcpOptions::default(),
+                            urg_ptr: 0,
                         },
                     ));
                 }
             }
 
-            // FIN from peer
-            if (seg.flags & TCP_FLAG_FIN) != 0 {
+            if t.state == State::FinWait1 && t.fin_acked() {
+                t.set_state(State::FinWait2);
+                // don't wait forever for a peer that never closes
+                t.timewait_until_ms = now_ms + t.cfg.time_wait_ms();
+            }
+            // FIN from peer, once everything before it has arrived
+            let fin_seq = seg.seq.wrapping_add(seg.payload.len() as u32);
+            if (seg.flags & TCP_FLAG_FIN) != 0 && fin_seq == t.rcv_nxt {
                 t.rcv_nxt = t.rcv_nxt.wrapping_add(1);
-                out.push((
-                    c.meta.clone(),
-                    WireSeg {
-                        src_port: t.local_port,
-                        dst_port: t.remote_port,
-                        seq: t.snd_nxt,
-                        ack: t.rcv_nxt,
-                        flags: TCP_FLAG_ACK,
-                        wnd: t.rcv_wnd as u16,
-                        payload: &[],
-                    },
-                ));
-                let fin = WireSeg {
-                    src_port: t.local_port,
-                    dst_port: t.remote_port,
-                    seq: t.snd_nxt,
-                    ack: t.rcv_nxt,
-                    flags: TCP_FLAG_FIN | TCP_FLAG_ACK,
-                    wnd: t.rcv_wnd as u16,
-                    payload: &[],
-                };
-                t.snd_nxt = t.snd_nxt.wrapping_add(1);
-                t.state = State::LastAck;
-                out.push((c.meta.clone(), fin));
+                t.ack_due_ms = 0;
+                out.push((c.meta.clone(), ack_seg(t)));
+                match t.state {
+                    State::Established => t.set_state(State::CloseWait),
+                    State::FinWait1 => t.set_state(State::Closing), // simultaneous close
+                    _ => enter_time_wait(t, now_ms),
+                }
             }
         }
-        State::LastAck => {
-            if (seg.flags & TCP_FLAG_ACK) != 0 && seg.ack == t.snd_nxt {
-                t.state = State::TimeWait;
-                t.timewait_until_ms = now_ms + TIME_WAIT_MS as u128;
-            }
+        State::Closing if t.fin_acked() => enter_time_wait(t, now_ms),
+        State::LastAck if t.fin_acked() => t.set_state(State::Closed),
+        // the peer missed our ACK of its FIN: ACK again and restart 2MSL
+        State::TimeWait if (seg.flags & TCP_FLAG_FIN) != 0 => {
+            out.push((c.meta.clone(), ack_seg(t)));
+            enter_time_wait(t, now_ms);
+        }
+        State::CloseWait | State::Closing | State::LastAck if (seg.flags & TCP_FLAG_FIN) != 0 => {
+            out.push((c.meta.clone(), ack_seg(t)));
         }
         _ => {}
     }
@@ -496,46 +1786,340 @@ fn conn_on_segment<'a>(
     out
 }
 
+// Sends new data in MSS-sized segments until cwnd, the peer's window,
+// Nagle or pacing says stop.
 fn conn_try_send(c: &mut TcpConn, now_ms: u128) -> Vec<(ConnMeta, WireSeg<'static>)> {
     let t = &mut c.tcb;
     let mut out = Vec::new();
 
-    let cwnd_room = t.cwnd.saturating_sub(t.flight);
-    let rwnd_room = (t.snd_wnd as usize).saturating_sub(t.flight);
-    let budget = min(cwnd_room, rwnd_room);
-
-    if budget == 0 || t.sendq.is_empty() {
+    if !matches!(t.state, State::Established | State::CloseWait) {
+        return out;
+    }
+    if t.snd_wnd == 0 && t.unsent() > 0 && t.unacked.is_empty() && t.persist_due_ms == 0 {
+        // peer closed its window: probe until it reopens
+        t.persist_due_ms = now_ms + t.rto.rto_ms as u128;
+    }
+    if t.pacing && now_ms < t.pace_next_ms {
         return out;
     }
-    let to_send = min(budget, min(t.sendq.len(), t.mss));
-    let mut v = vec![0u8; to_send];
-    for i in 0..to_send {
-        if let Some(b) = t.sendq.pop_front() {
-            v[i] = b;
+
+    // RFC 3042 limited transmit: each of the first two dup-ACKs lets one
+    // more new segment out beyond cwnd
+    let limited = if t.dupacks < t.cfg.dupack_threshold { t.dupacks as usize * t.mss } else { 0 };
+    let cwnd = match t.cc {
+        CongestionControl::Reno => t.cwnd + limited,
+        CongestionControl::Unlimited => usize::MAX,
+    };
+    let burst = if t.pacing { PACING_BURST } else { usize::MAX };
+
+    while out.len() < burst {
+        let unsent = t.unsent();
+        let room = min(cwnd.saturating_sub(t.flight), (t.snd_wnd as usize).saturating_sub(t.flight));
+        let to_send = min(room, min(unsent, t.mss));
+        if to_send == 0 {
+            break;
+        }
+        // Nagle (RFC 896): no runt segments while earlier data is unacked;
+        // urgent data is not held back
+        if to_send < t.mss && !t.nodelay && t.snd_up.is_none() && t.snd_nxt != t.snd_una {
+            break;
+        }
+        let mut payload = vec![0u8; to_send];
+        t.sendq.peek_at(t.snd_nxt.wrapping_sub(t.snd_una) as usize, &mut payload);
+
+        let (urg, urg_ptr) = urgent_fields(t, t.snd_nxt);
+        let seg = WireSeg {
+            src_port: t.local_port,
+            dst_port: t.remote_port,
+            seq: t.snd_nxt,
+            ack: t.rcv_nxt,
+            flags: TCP_FLAG_ACK | TCP_FLAG_PSH | urg,
+            wnd: t.rcv_wnd as u16,
+            payload: Cow::Owned(payload),
+            opts: TcpOptions::default(),
+            urg_ptr,
+        };
+
+        t.unacked.insert(t.snd_nxt, SentSeg { len: to_send, sent_ms: now_ms, retransmitted: false, fin: false });
+        if t.rto_due_ms == 0 {
+            // 5.1: start the timer if it is not running
+            t.rto_due_ms = now_ms + t.rto.rto_ms as u128;
+        }
+        t.snd_nxt = t.snd_nxt.wrapping_add(to_send as u32);
+        t.flight += to_send;
+        out.push((c.meta.clone(), seg));
+    }
+
+    // FIN once everything queued before close() has gone out
+    if t.fin_queued && t.fin_seq.is_none() && t.unsent() == 0 && out.len() < burst {
+        let fin = WireSeg {
+            src_port: t.local_port,
+            dst_port: t.remote_port,
+            seq: t.snd_nxt,
+            ack: t.rcv_nxt,
+            flags: TCP_FLAG_FIN | TCP_FLAG_ACK,
+            wnd: t.rcv_wnd as u16,
+            payload: Cow::Borrowed(&[]),
+            opts: TcpOptions::default(),
+            urg_ptr: 0,
+        };
+        t.unacked.insert(t.snd_nxt, SentSeg { len: 0, sent_ms: now_ms, retransmitted: false, fin: true });
+        if t.rto_due_ms == 0 {
+            t.rto_due_ms = now_ms + t.rto.rto_ms as u128;
         }
+        t.fin_seq = Some(t.snd_nxt);
+        t.snd_nxt = t.snd_nxt.wrapping_add(1);
+        t.set_state(if t.state == State::CloseWait { State::LastAck } else { State::FinWait1 });
+        out.push((c.meta.clone(), fin));
+    }
+
+    if t.pacing && out.len() == burst && t.unsent() > 0 {
+        // next burst after the time this one takes at cwnd-per-RTT
+        let srtt = t.rto.srtt_ms().unwrap_or(0) as u128;
+        let gap = srtt * (burst * t.mss) as u128 / t.cwnd.max(1) as u128;
+        t.pace_next_ms = now_ms + gap.max(1);
+    }
+    out
+}
+
+// RFC 6191: a SYN may reopen a TIME-WAIT tuple only if it is provably from
+// a new incarnation, by timestamp when the old one used them, otherwise by
+// sequence number (RFC 1122 4.2.2.13).
+fn time_wait_reusable(t: &Tcb, syn: &TcpSeg<'_>) -> bool {
+    match (t.ts_ok, syn.opts.timestamps) {
+        (true, Some((val, _))) => seq_before(t.ts_recent, val),
+        (true, None) => false,
+        (false, _) => seq_before(t.rcv_nxt, syn.seq),
     }
-    let payload_box = v.into_boxed_slice();
-    let payload: &'static [u8] = Box::leak(payload_box);
+}
 
-    let seg = WireSeg {
+fn enter_time_wait(t: &mut Tcb, now_ms: u128) {
+    t.set_state(State::TimeWait);
+    t.timewait_until_ms = now_ms + t.cfg.time_wait_ms();
+}
+
+// Bare ACK of everything received so far.
+fn ack_seg(t: &Tcb) -> WireSeg<'static> {
+    WireSeg {
         src_port: t.local_port,
         dst_port: t.remote_port,
         seq: t.snd_nxt,
         ack: t.rcv_nxt,
-        flags: TCP_FLAG_ACK | TCP_FLAG_PSH,
+        flags: TCP_FLAG_ACK,
         wnd: t.rcv_wnd as u16,
-        payload,
-    };
+        payload: Cow::Borrowed(&[]),
+        opts: TcpOptions::default(),
+        urg_ptr: 0,
+    }
+}
 
-    t.unacked.insert(t.snd_nxt, (to_send, now_ms));
-    t.snd_nxt = t.snd_nxt.wrapping_add(to_send as u32);
-    t.flight += to_send;
+fn summary(seg: &TcpSeg<'_>) -> SegSummary {
+    SegSummary { seq: seg.seq, ack: seg.ack, flags: seg.flags, len: seg.payload.len(), wnd: seg.wnd }
+}
 
-    out.push((c.meta.clone(), seg));
-    out
+// Appends in-order bytes (starting at rcv_nxt) to app_read; returns how many
+// were taken. Sets the urgent mark when the last urgent byte goes by and,
+// unless oob_inline, pulls that byte out of the stream as BSD does.
+fn deliver(t: &mut Tcb, data: &[u8]) -> usize {
+    let mut taken = 0;
+    if let Some(up) = t.rcv_up {
+        let at = up.wrapping_sub(1).wrapping_sub(t.rcv_nxt) as usize;
+        if at < data.len() {
+            taken = t.app_read.write(&data[..at]);
+            if taken < at {
+                return taken;
+            }
+            t.rcv_up = None;
+            t.urg_mark = Some(t.app_read.len());
+            if !t.oob_inline {
+                t.oob = Some(data[at]);
+                taken += 1;
+            }
+        }
+    }
+    taken + t.app_read.write(&data[taken..])
+}
+
+// URG flag and pointer for a segment starting at `seq`: set on every
+// segment that begins before the urgent point, the pointer saturating
+// when the point is more than 64K ahead.
+fn urgent_fields(t: &Tcb, seq: u32) -> (u16, u16) {
+    match t.snd_up {
+        Some(up) if seq_before(seq, up) => (TCP_FLAG_URG, min(up.wrapping_sub(seq), 0xffff) as u16),
+        _ => (0, 0),
+    }
+}
+
+// Copy of already-sent bytes [seq, seq + len) for retransmission.
+fn unacked_payload(t: &Tcb, seq: u32, len: usize) -> Vec<u8> {
+    let mut v = vec![0u8; len];
+    let n = t.sendq.peek_at(seq.wrapping_sub(t.snd_una) as usize, &mut v);
+    v.truncate(n);
+    v
+}
+
+// A local port is unusable towards a peer if something listens on it, or
+// the 4-tuple is still held by a connection (TIME-WAIT included).
+fn port_in_use(
+    listeners: &HashMap<ListenAddr, Listener>,
+    conns: &HashMap<FourTuple, TcpConn>,
+    local_ip: [u8; 4],
+    port: u16,
+    remote_ip: [u8; 4],
+    remote_port: u16,
+) -> bool {
+    listeners.contains_key(&ListenAddr::new(local_ip, port))
+        || listeners.contains_key(&ListenAddr::any(port))
+        || conns.contains_key(&FourTuple {
+            src_ip: remote_ip,
+            dst_ip: local_ip,
+            src_port: remote_port,
+            dst_port: port,
+        })
+}
+
+fn syn_seg(t: &Tcb) -> WireSeg<'static> {
+    WireSeg {
+        src_port: t.local_port,
+        dst_port: t.remote_port,
+        seq: t.iss,
+        ack: 0,
+        flags: TCP_FLAG_SYN,
+        wnd: t.rcv_wnd as u16,
+        payload: Cow::Borrowed(&[]),
+        opts: TcpOptions { mss: Some(t.cfg.mss as u16), ..TcpOptions::default() },
+        urg_ptr: 0,
+    }
+}
+
+fn synack_seg(t: &Tcb) -> WireSeg<'static> {
+    WireSeg { ack: t.rcv_nxt, flags: TCP_FLAG_SYN | TCP_FLAG_ACK, ..syn_seg(t) }
+}
+
+// RFC 9293 3.10.7.1: the RST answering a segment that belongs to no
+// connection. Never one for a RST.
+fn reset_for(key: FourTuple, seg: &TcpSeg<'_>) -> Option<(ConnMeta, WireSeg<'static>)> {
+    if seg.flags & TCP_FLAG_RST != 0 {
+        return None;
+    }
+    let (seq, ack, flags) = if seg.flags & TCP_FLAG_ACK != 0 {
+        (seg.ack, 0, TCP_FLAG_RST)
+    } else {
+        let len = seg.payload.len() as u32
+            + (seg.flags & TCP_FLAG_SYN != 0) as u32
+            + (seg.flags & TCP_FLAG_FIN != 0) as u32;
+        (0, seg.seq.wrapping_add(len), TCP_FLAG_RST | TCP_FLAG_ACK)
+    };
+    let meta = ConnMeta { key, ip_src: key.dst_ip, ip_dst: key.src_ip };
+    let rst = WireSeg {
+        src_port: seg.dst_port,
+        dst_port: seg.src_port,
+        seq,
+        ack,
+        flags,
+        wnd: 0,
+        payload: Cow::Borrowed(&[]),
+        opts: TcpOptions::default(),
+        urg_ptr: 0,
+    };
+    Some((meta, rst))
 }
 
 #[inline]
 fn seq_before(a: u32, b: u32) -> bool {
     (a as i32).wrapping_sub(b as i32) < 0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: [u8; 4] = [10, 0, 0, 2];
+    const SERVER: [u8; 4] = [10, 0, 0, 1];
+
+    type Out = Vec<(ConnMeta, WireSeg<'static>)>;
+
+    // Runs every segment through the wire format into `to`; returns its replies.
+    fn deliver(to: &mut TcpStack, segs: Out, now: u128) -> Out {
+        let mut back = Vec::new();
+        for (meta, seg) in segs {
+            let bytes = serialize_tcp(&seg, meta.ip_src, meta.ip_dst);
+            let rx = parse_tcp(meta.ip_src, meta.ip_dst, &bytes).unwrap();
+            let key = FourTuple { src_ip: meta.ip_src, dst_ip: meta.ip_dst, src_port: rx.src_port, dst_port: rx.dst_port };
+            back.extend(to.on_segment(now, key, &rx));
+        }
+        back
+    }
+
+    #[test]
+    fn ack_above_snd_nxt_is_dropped() {
+        let (mut server, mut client) = (TcpStack::new(), TcpStack::new());
+        server.listen(80);
+        let (h, syn) = client.connect(CLIENT, SERVER, 80, 1).unwrap();
+        let synack = deliver(&mut server, syn, 2);
+        let ack = deliver(&mut client, synack, 3);
+        deliver(&mut server, ack, 4);
+        // a window's worth in flight and the rest queued behind it
+        let (n, _) = client.send_app(h, &[7; 64 * 1024], 5);
+        assert!(n > 0);
+
+        let t = &client.conns[&h.0].tcb;
+        let before = (t.sendq.len(), t.snd_una, t.snd_nxt, t.flight);
+        assert!(before.0 > before.3, "some data must still be unsent");
+        let forged = TcpSeg {
+            src_port: 80,
+            dst_port: h.0.dst_port,
+            seq: t.rcv_nxt,
+            ack: t.snd_nxt.wrapping_add(1000),
+            flags: TCP_FLAG_ACK,
+            wnd: 65535,
+            urg_ptr: 0,
+            opts: TcpOptions::default(),
+            payload: &[],
+        };
+        let rcv_nxt = t.rcv_nxt;
+        let out = client.on_segment(6, h.0, &forged);
+
+        let t = &client.conns[&h.0].tcb;
+        assert_eq!((t.sendq.len(), t.snd_una, t.snd_nxt, t.flight), before);
+        assert_eq!(out.len(), 1);
+        let (_, reply) = &out[0];
+        assert_eq!((reply.flags, reply.seq, reply.ack), (TCP_FLAG_ACK, before.2, rcv_nxt));
+    }
+
+    fn bare_ack(src_port: u16, seq: u32, ack: u32) -> TcpSeg<'static> {
+        TcpSeg {
+            src_port,
+            dst_port: 80,
+            seq,
+            ack,
+            flags: TCP_FLAG_ACK,
+            wnd: 65535,
+            urg_ptr: 0,
+            opts: TcpOptions::default(),
+            payload: &[],
+        }
+    }
+
+    #[test]
+    fn stray_ack_to_a_listener_is_reset() {
+        let mut server = TcpStack::new();
+        server.listen(80);
+        let key = FourTuple { src_ip: CLIENT, dst_ip: SERVER, src_port: 40000, dst_port: 80 };
+        let out = server.on_segment(1, key, &bare_ack(40000, 100, 5000));
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].1.flags, out[0].1.seq), (TCP_FLAG_RST, 5000));
+
+        // once cookies are out, a forged one is reset too, not dropped
+        server.syn_backlog = 0;
+        let mut syn = bare_ack(40001, 100, 0);
+        syn.flags = TCP_FLAG_SYN;
+        let synack = server.on_segment(2, FourTuple { src_port: 40001, ..key }, &syn);
+        assert_eq!(synack[0].1.flags, TCP_FLAG_SYN | TCP_FLAG_ACK);
+        let out = server.on_segment(3, key, &bare_ack(40000, 100, 5000));
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].1.flags, TCP_FLAG_RST);
+        assert_eq!(server.syncookies.stats.failed, 1);
+        assert!(server.conns.is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+// TCP options codec (RFC 793 / 9293 section 3.2)
+
+pub const OPT_END: u8 = 0;
+pub const OPT_NOP: u8 = 1;
+pub const OPT_MSS: u8 = 2;
+pub const OPT_TIMESTAMPS: u8 = 8; // RFC 7323 section 3
+pub const OPT_FASTOPEN: u8 = 34; // RFC 7413 section 4.1.1
+
+// TFO cookie: 4..=16 bytes, even length. An empty one is a cookie request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TfoCookie {
+    len: u8,
+    bytes: [u8; 16],
+}
+
+impl TfoCookie {
+    pub fn request() -> Self {
+        Self::default()
+    }
+    pub fn new(b: &[u8]) -> Option<Self> {
+        if !(b.is_empty() || (4..=16).contains(&b.len()) && b.len().is_multiple_of(2)) {
+            return None;
+        }
+        let mut bytes = [0; 16];
+        bytes[..b.len()].copy_from_slice(b);
+        Some(Self { len: b.len() as u8, bytes })
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+    pub fn is_request(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    pub mss: Option<u16>,
+    pub fastopen: Option<TfoCookie>,
+    pub timestamps: Option<(u32, u32)>, // (TSval, TSecr)
+}
+
+impl TcpOptions {
+    // Unknown kinds are skipped; a malformed length ends parsing.
+    pub fn parse(mut b: &[u8]) -> Self {
+        let mut o = TcpOptions::default();
+        while let Some(&kind) = b.first() {
+            match kind {
+                OPT_END => break,
+                OPT_NOP => {
+                    b = &b[1..];
+                    continue;
+                }
+                _ => {}
+            }
+            if b.len() < 2 {
+                break;
+            }
+            let len = b[1] as usize;
+            if len < 2 || len > b.len() {
+                break;
+            }
+            let body = &b[2..len];
+            match kind {
+                OPT_MSS if body.len() == 2 => o.mss = Some(u16::from_be_bytes([body[0], body[1]])),
+                OPT_FASTOPEN => o.fastopen = TfoCookie::new(body),
+                OPT_TIMESTAMPS if body.len() == 8 => {
+                    let val = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+                    let ecr = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+                    o.timestamps = Some((val, ecr));
+                }
+                _ => {}
+            }
+            b = &b[len..];
+        }
+        o
+    }
+
+    // Appends the encoded options, NOP-padded to a 32-bit boundary.
+    pub fn write(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        if let Some(mss) = self.mss {
+            out.push(OPT_MSS);
+            out.push(4);
+            out.extend_from_slice(&mss.to_be_bytes());
+        }
+        if let Some(c) = self.fastopen {
+            out.push(OPT_FASTOPEN);
+            out.push(2 + c.len);
+            out.extend_from_slice(c.as_slice());
+        }
+        if let Some((val, ecr)) = self.timestamps {
+            // the usual NOP, NOP, TS layout keeps the values 32-bit aligned
+            out.extend_from_slice(&[OPT_NOP, OPT_NOP, OPT_TIMESTAMPS, 10]);
+            out.extend_from_slice(&val.to_be_bytes());
+            out.extend_from_slice(&ecr.to_be_bytes());
+        }
+        while !(out.len() - start).is_multiple_of(4) {
+            out.push(OPT_NOP);
+        }
+    }
+}
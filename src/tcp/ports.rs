@@ -0,0 +1,153 @@
+// Ephemeral port selection for active opens (RFC 6056 algorithm 4,
+// "double-hash port selection").
+//
+// F(addrs, secret1) gives each destination its own starting offset into the
+// range, so ports used towards one peer say nothing about another. A small
+// table of counters indexed by G(addrs, secret2) advances the offset on every
+// attempt, so reconnecting to the same peer walks forward instead of retrying
+// the same port.
+
+use std::hash::{BuildHasher, RandomState};
+
+pub const EPHEMERAL_MIN: u16 = 49152; // IANA dynamic range
+pub const EPHEMERAL_MAX: u16 = 65535;
+const TABLE_LENGTH: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortError {
+    Exhausted,    // every port in the range is bound or in use towards this peer
+    InvalidRange, // min > max or min == 0
+}
+
+pub struct EphemeralPorts {
+    min: u16,
+    max: u16,
+    secret1: RandomState,
+    secret2: RandomState,
+    table: Vec<u32>,
+}
+
+impl Default for EphemeralPorts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EphemeralPorts {
+    pub fn new() -> Self {
+        Self {
+            min: EPHEMERAL_MIN,
+            max: EPHEMERAL_MAX,
+            secret1: RandomState::new(),
+            secret2: RandomState::new(),
+            table: vec![0; TABLE_LENGTH],
+        }
+    }
+
+    pub fn range(&self) -> (u16, u16) {
+        (self.min, self.max)
+    }
+
+    pub fn set_range(&mut self, min: u16, max: u16) -> Result<(), PortError> {
+        if min == 0 || min > max {
+            return Err(PortError::InvalidRange);
+        }
+        self.min = min;
+        self.max = max;
+        Ok(())
+    }
+
+    // Picks a local port towards remote_ip:remote_port. `in_use(port)` must
+    // report ports that are bound, or whose 4-tuple is still taken by a live
+    // or TIME-WAIT connection.
+    pub fn allocate(
+        &mut self,
+        local_ip: [u8; 4],
+        remote_ip: [u8; 4],
+        remote_port: u16,
+        in_use: impl Fn(u16) -> bool,
+    ) -> Result<u16, PortError> {
+        let num = (self.max - self.min) as u32 + 1;
+        let ids = (local_ip, remote_ip, remote_port);
+        let offset = self.secret1.hash_one(ids) as u32;
+        let index = (self.secret2.hash_one(ids) as usize) % TABLE_LENGTH;
+        for _ in 0..num {
+            let next = self.table[index];
+            self.table[index] = next.wrapping_add(1);
+            let port = self.min + (offset.wrapping_add(next) % num) as u16;
+            if !in_use(port) {
+                return Ok(port);
+            }
+        }
+        Err(PortError::Exhausted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: [u8; 4] = [10, 0, 0, 1];
+    const PEER: [u8; 4] = [10, 0, 0, 2];
+
+    #[test]
+    fn stays_in_range() {
+        let mut p = EphemeralPorts::new();
+        p.set_range(50_000, 50_009).unwrap();
+        let mut seen = Vec::new();
+        for _ in 0..10 {
+            let port = p.allocate(LOCAL, PEER, 80, |_| false).unwrap();
+            assert!((50_000..=50_009).contains(&port));
+            seen.push(port);
+        }
+        // reconnecting walks through the whole range before repeating
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 10);
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        let mut p = EphemeralPorts::new();
+        assert_eq!(p.set_range(0, 100), Err(PortError::InvalidRange));
+        assert_eq!(p.set_range(2000, 1000), Err(PortError::InvalidRange));
+        assert_eq!(p.range(), (EPHEMERAL_MIN, EPHEMERAL_MAX));
+        assert_eq!(p.set_range(1000, 1000), Ok(()));
+        assert_eq!(p.allocate(LOCAL, PEER, 80, |_| false), Ok(1000));
+    }
+
+    #[test]
+    fn skips_ports_in_use() {
+        let mut p = EphemeralPorts::new();
+        p.set_range(50_000, 50_009).unwrap();
+        for _ in 0..5 {
+            assert_eq!(p.allocate(LOCAL, PEER, 80, |port| port != 50_007), Ok(50_007));
+        }
+    }
+
+    #[test]
+    fn reports_exhaustion() {
+        let mut p = EphemeralPorts::new();
+        p.set_range(50_000, 50_009).unwrap();
+        assert_eq!(p.allocate(LOCAL, PEER, 80, |_| true), Err(PortError::Exhausted));
+        // and recovers once a port frees up
+        assert_eq!(p.allocate(LOCAL, PEER, 80, |port| port != 50_003), Ok(50_003));
+    }
+
+    #[test]
+    fn destinations_get_their_own_sequence() {
+        let mut p = EphemeralPorts::new();
+        let mut run = |remote_port| -> Vec<u16> {
+            (0..4).map(|_| p.allocate(LOCAL, PEER, remote_port, |_| false).unwrap()).collect()
+        };
+        let (http, https) = (run(80), run(443));
+        assert_ne!(http, https);
+        // each one counts up from its own offset
+        for seq in [&http, &https] {
+            for w in seq.windows(2) {
+                let next = if w[0] == EPHEMERAL_MAX { EPHEMERAL_MIN } else { w[0] + 1 };
+                assert_eq!(w[1], next);
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Out-of-order receive queue.
+//
+// Holds disjoint, non-adjacent byte ranges ahead of rcv_nxt. Ranges are kept
+// sorted by their distance from rcv_nxt rather than by raw sequence number,
+// so a window that straddles 2^32 never reorders them. Overlapping or
+// duplicated retransmissions are trimmed and merged on insert, and nothing
+// is stored past rcv_nxt + cap, which bounds the memory a peer can pin.
+
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+
+#[derive(Clone, Debug)]
+pub struct Reassembly {
+    ranges: VecDeque<(u32, Vec<u8>)>, // (start seq, bytes)
+    bytes: usize,
+    pub cap: usize,
+}
+
+impl Reassembly {
+    pub fn new(cap: usize) -> Self {
+        Self { ranges: VecDeque::new(), bytes: 0, cap }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    // Bytes currently buffered.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // Buffers `data` at `seq`. Bytes before rcv_nxt or beyond rcv_nxt + cap
+    // are dropped. Returns how many previously unseen bytes were stored.
+    pub fn insert(&mut self, rcv_nxt: u32, seq: u32, data: &[u8]) -> usize {
+        self.trim_front(rcv_nxt);
+        let off = seq.wrapping_sub(rcv_nxt) as i32;
+        let (mut start, data) = if off < 0 {
+            let skip = off.unsigned_abs() as usize;
+            if skip >= data.len() {
+                return 0;
+            }
+            (0, &data[skip..])
+        } else {
+            (off as usize, data)
+        };
+        if start >= self.cap || data.is_empty() {
+            return 0;
+        }
+        let mut buf = data[..min(data.len(), self.cap - start)].to_vec();
+        let before = self.bytes;
+
+        // merge with every range we overlap or touch
+        let mut i = 0;
+        while i < self.ranges.len() {
+            let a = self.ranges[i].0.wrapping_sub(rcv_nxt) as usize;
+            let b = a + self.ranges[i].1.len();
+            if b < start {
+                i += 1;
+                continue;
+            }
+            if a > start + buf.len() {
+                break;
+            }
+            let (_, old) = self.ranges.remove(i).unwrap();
+            self.bytes -= old.len();
+            let lo = min(a, start);
+            let hi = max(b, start + buf.len());
+            let mut merged = vec![0u8; hi - lo];
+            merged[start - lo..start - lo + buf.len()].copy_from_slice(&buf);
+            merged[a - lo..b - lo].copy_from_slice(&old);
+            start = lo;
+            buf = merged;
+        }
+        self.bytes += buf.len();
+        self.ranges.insert(i, (rcv_nxt.wrapping_add(start as u32), buf));
+        self.bytes - before
+    }
+
+    // Removes and returns the bytes that now continue the stream at rcv_nxt.
+    pub fn pop_ready(&mut self, rcv_nxt: u32) -> Option<Vec<u8>> {
+        self.trim_front(rcv_nxt);
+        if self.ranges.front()?.0 != rcv_nxt {
+            return None;
+        }
+        let (_, data) = self.ranges.pop_front()?;
+        self.bytes -= data.len();
+        Some(data)
+    }
+
+    // Once rcv_nxt has moved (in-order data that overlapped what we held),
+    // drop ranges it fully covers and cut the one it lands in.
+    fn trim_front(&mut self, rcv_nxt: u32) {
+        while let Some((seq, data)) = self.ranges.front_mut() {
+            let off = seq.wrapping_sub(rcv_nxt) as i32;
+            if off >= 0 {
+                return;
+            }
+            let skip = off.unsigned_abs() as usize;
+            if skip >= data.len() {
+                self.bytes -= data.len();
+                self.ranges.pop_front();
+                continue;
+            }
+            data.drain(..skip);
+            self.bytes -= skip;
+            *seq = rcv_nxt;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Byte i of the test stream, so merged ranges can be checked by content.
+    fn stream(seq: u32, len: usize) -> Vec<u8> {
+        (0..len).map(|i| seq.wrapping_add(i as u32) as u8).collect()
+    }
+
+    fn insert(r: &mut Reassembly, rcv_nxt: u32, off: u32, len: usize) -> usize {
+        let seq = rcv_nxt.wrapping_add(off);
+        r.insert(rcv_nxt, seq, &stream(seq, len))
+    }
+
+    #[test]
+    fn wraps_around_zero() {
+        let rcv_nxt = u32::MAX - 2;
+        let mut r = Reassembly::new(1000);
+        // the later range lies past 2^32, the earlier one before it
+        assert_eq!(insert(&mut r, rcv_nxt, 10, 10), 10);
+        assert_eq!(insert(&mut r, rcv_nxt, 1, 4), 4);
+        assert_eq!(r.ranges.len(), 2);
+        assert_eq!(r.ranges[0].0, rcv_nxt.wrapping_add(1));
+        assert_eq!(r.ranges[1].0, 7);
+        // filling the gap across the wrap leaves one range
+        assert_eq!(insert(&mut r, rcv_nxt, 5, 5), 5);
+        assert_eq!(r.ranges.len(), 1);
+        let seq = rcv_nxt.wrapping_add(1);
+        assert_eq!(r.pop_ready(seq), Some(stream(seq, 19)));
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn partial_overlap() {
+        let mut r = Reassembly::new(1000);
+        assert_eq!(insert(&mut r, 0, 10, 10), 10);
+        // only the new tail counts, on either side
+        assert_eq!(insert(&mut r, 0, 15, 10), 5);
+        assert_eq!(insert(&mut r, 0, 5, 10), 5);
+        assert_eq!(r.bytes(), 20);
+        assert_eq!(r.ranges.len(), 1);
+        assert_eq!(r.ranges[0], (5, stream(5, 20)));
+    }
+
+    #[test]
+    fn full_overlap() {
+        let mut r = Reassembly::new(1000);
+        assert_eq!(insert(&mut r, 0, 10, 10), 10);
+        assert_eq!(insert(&mut r, 0, 10, 10), 0);
+        assert_eq!(insert(&mut r, 0, 12, 5), 0);
+        // a range that covers several held ones swallows them
+        assert_eq!(insert(&mut r, 0, 30, 5), 5);
+        assert_eq!(insert(&mut r, 0, 5, 40), 25);
+        assert_eq!(r.bytes(), 40);
+        assert_eq!(r.ranges.len(), 1);
+        assert_eq!(r.ranges[0], (5, stream(5, 40)));
+    }
+
+    #[test]
+    fn merges_adjacent_ranges() {
+        let mut r = Reassembly::new(1000);
+        insert(&mut r, 0, 10, 10);
+        insert(&mut r, 0, 30, 10);
+        assert_eq!(r.ranges.len(), 2);
+        // touches both neighbours without overlapping either
+        assert_eq!(insert(&mut r, 0, 20, 10), 10);
+        assert_eq!(r.ranges.len(), 1);
+        assert_eq!(r.ranges[0], (10, stream(10, 30)));
+    }
+
+    #[test]
+    fn drops_data_before_rcv_nxt() {
+        let mut r = Reassembly::new(1000);
+        assert_eq!(insert(&mut r, 100, 0u32.wrapping_sub(10), 5), 0);
+        assert_eq!(insert(&mut r, 100, 0u32.wrapping_sub(5), 10), 5);
+        assert_eq!(r.ranges[0], (100, stream(100, 5)));
+        // rcv_nxt moving into a held range cuts it
+        assert_eq!(r.pop_ready(102), Some(stream(102, 3)));
+    }
+
+    #[test]
+    fn respects_the_cap() {
+        let mut r = Reassembly::new(100);
+        assert_eq!(insert(&mut r, 0, 90, 20), 10);
+        assert_eq!(insert(&mut r, 0, 100, 5), 0);
+        assert_eq!(insert(&mut r, 0, 500, 5), 0);
+        assert_eq!(r.bytes(), 10);
+        // the cap counts from rcv_nxt, so it moves along with it
+        assert_eq!(insert(&mut r, 50, 50, 5), 5);
+    }
+
+    #[test]
+    fn drains_in_order() {
+        let mut r = Reassembly::new(1000);
+        let rcv_nxt = 1000;
+        for (off, len) in [(300, 100), (100, 50), (200, 100), (150, 50)] {
+            insert(&mut r, rcv_nxt, off, len);
+        }
+        assert_eq!(r.pop_ready(rcv_nxt), None); // the gap at rcv_nxt is still open
+        insert(&mut r, rcv_nxt, 0, 100);
+        let mut got = Vec::new();
+        let mut next = rcv_nxt;
+        while let Some(data) = r.pop_ready(next) {
+            next += data.len() as u32;
+            got.extend(data);
+        }
+        assert_eq!(got, stream(rcv_nxt, 400));
+        assert!(r.is_empty());
+        assert_eq!(r.bytes(), 0);
+    }
+}
@@ -0,0 +1,150 @@
+// SYN cookies (RFC 4987 section 3.6)
+//
+// When a listener's SYN backlog is full we stop allocating a TcpConn per SYN.
+// Instead the SYN-ACK's ISN carries everything needed to rebuild the Tcb
+// from the peer's final ACK:
+//
+//   | 5 bits counter | 3 bits MSS index | 24 bits keyed hash |
+//
+// The counter ticks every 64 s; a cookie is accepted for two ticks.
+
+use std::hash::{BuildHasher, RandomState};
+use super::FourTuple;
+
+const COUNTER_MS: u128 = 64_000;
+const MAX_AGE: u32 = 2;
+// Only honour cookie ACKs this long after the backlog last overflowed.
+pub const OVERFLOW_WINDOW_MS: u128 = (MAX_AGE as u128 + 1) * COUNTER_MS;
+const MSS_TABLE: [u16; 8] = [536, 1200, 1300, 1360, 1400, 1440, 1452, 1460];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SynCookieStats {
+    pub sent: u64,
+    pub validated: u64,
+    pub failed: u64,
+}
+
+pub struct SynCookies {
+    secret: RandomState,
+    last_overflow_ms: Option<u128>,
+    pub stats: SynCookieStats,
+}
+
+impl Default for SynCookies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SynCookies {
+    pub fn new() -> Self {
+        Self { secret: RandomState::new(), last_overflow_ms: None, stats: SynCookieStats::default() }
+    }
+
+    // ISN for a SYN-ACK answering `key` whose SYN carried `peer_isn`.
+    // Returns the ISN and the (rounded down) MSS that it encodes.
+    pub fn make(&mut self, key: &FourTuple, peer_isn: u32, mss: u16, now_ms: u128) -> (u32, u16) {
+        self.last_overflow_ms = Some(now_ms);
+        let idx = MSS_TABLE.iter().rposition(|&m| m <= mss).unwrap_or(0);
+        let t = counter(now_ms);
+        let isn = ((t & 0x1f) << 27) | ((idx as u32) << 24) | self.mac(key, peer_isn, t);
+        self.stats.sent += 1;
+        (isn, MSS_TABLE[idx])
+    }
+
+    // Validates the ACK of a cookie SYN-ACK; returns the encoded MSS.
+    pub fn check(&mut self, key: &FourTuple, seg_seq: u32, seg_ack: u32, now_ms: u128) -> Option<u16> {
+        match self.last_overflow_ms {
+            Some(at) if now_ms.saturating_sub(at) <= OVERFLOW_WINDOW_MS => {}
+            _ => return None,
+        }
+        let cookie = seg_ack.wrapping_sub(1);
+        let peer_isn = seg_seq.wrapping_sub(1);
+        let now_t = counter(now_ms);
+        for age in 0..=MAX_AGE {
+            let t = now_t.wrapping_sub(age);
+            if (cookie >> 27) == (t & 0x1f) && (cookie & 0x00ff_ffff) == self.mac(key, peer_isn, t) {
+                self.stats.validated += 1;
+                return Some(MSS_TABLE[((cookie >> 24) & 0x7) as usize]);
+            }
+        }
+        self.stats.failed += 1;
+        None
+    }
+
+    fn mac(&self, key: &FourTuple, peer_isn: u32, t: u32) -> u32 {
+        (self.secret.hash_one((key, peer_isn, t)) & 0x00ff_ffff) as u32
+    }
+}
+
+fn counter(now_ms: u128) -> u32 {
+    (now_ms / COUNTER_MS) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: FourTuple = FourTuple { src_ip: [10, 0, 0, 2], dst_ip: [10, 0, 0, 1], src_port: 40000, dst_port: 80 };
+    const PEER_ISN: u32 = 0xdead_beef;
+
+    // What check() is given: the peer's final ACK.
+    fn ack_of(isn: u32) -> (u32, u32) {
+        (PEER_ISN.wrapping_add(1), isn.wrapping_add(1))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut c = SynCookies::new();
+        let (isn, mss) = c.make(&KEY, PEER_ISN, 1460, 5_000);
+        let (seq, ack) = ack_of(isn);
+        assert_eq!(c.check(&KEY, seq, ack, 6_000), Some(mss));
+        assert_eq!(c.stats, SynCookieStats { sent: 1, validated: 1, failed: 0 });
+    }
+
+    #[test]
+    fn mss_rounds_down_to_the_table() {
+        let mut c = SynCookies::new();
+        for (offered, encoded) in [(1460, 1460), (9000, 1460), (1450, 1440), (1300, 1300), (1000, 536), (100, 536)] {
+            let (isn, mss) = c.make(&KEY, PEER_ISN, offered, 0);
+            assert_eq!(mss, encoded, "offered {offered}");
+            let (seq, ack) = ack_of(isn);
+            assert_eq!(c.check(&KEY, seq, ack, 0), Some(encoded));
+        }
+    }
+
+    #[test]
+    fn stale_counter() {
+        let mut c = SynCookies::new();
+        let (isn, _) = c.make(&KEY, PEER_ISN, 1460, 0);
+        let (seq, ack) = ack_of(isn);
+        // accepted while the counter is at most MAX_AGE ticks on
+        assert!(c.check(&KEY, seq, ack, MAX_AGE as u128 * COUNTER_MS).is_some());
+        assert_eq!(c.check(&KEY, seq, ack, (MAX_AGE as u128 + 1) * COUNTER_MS), None);
+    }
+
+    #[test]
+    fn only_after_an_overflow() {
+        let mut c = SynCookies::new();
+        assert_eq!(c.check(&KEY, 1, 1, 0), None);
+        let (isn, _) = c.make(&KEY, PEER_ISN, 1460, 0);
+        let (seq, ack) = ack_of(isn);
+        assert_eq!(c.check(&KEY, seq, ack, OVERFLOW_WINDOW_MS + 1), None);
+    }
+
+    #[test]
+    fn forged_cookie() {
+        let mut c = SynCookies::new();
+        let (isn, _) = c.make(&KEY, PEER_ISN, 1460, 0);
+        let (seq, ack) = ack_of(isn);
+        // a flipped hash bit, another tuple, another peer ISN
+        assert_eq!(c.check(&KEY, seq, ack ^ 1, 0), None);
+        let other = FourTuple { src_port: 40001, ..KEY };
+        assert_eq!(c.check(&other, seq, ack, 0), None);
+        assert_eq!(c.check(&KEY, seq.wrapping_add(1), ack, 0), None);
+        // and one keyed with another secret
+        let (forged, _) = SynCookies::new().make(&KEY, PEER_ISN, 1460, 0);
+        assert_eq!(c.check(&KEY, seq, forged.wrapping_add(1), 0), None);
+        assert_eq!(c.stats.failed, 4);
+    }
+}
@@ -0,0 +1,253 @@
+// Hierarchical timing wheel (Varghese & Lauck) with 1 ms ticks.
+//
+// Four levels of 64 slots cover 64^4 ms (~4.6 h); later deadlines are
+// parked in the last slot of the top level and re-filed as time passes.
+// Each key has at most one live deadline: schedule() replaces it, and older
+// wheel entries for the key are dropped lazily when their slot comes up.
+// Deadlines already behind the wheel fire on the next expire() call.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const MASK: u64 = SLOTS as u64 - 1;
+const LEVELS: usize = 4;
+const SPAN: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+pub struct TimerWheel<K> {
+    tick: u64,                  // next tick to process; every deadline before it has fired
+    slots: Vec<Vec<(K, u64)>>,  // LEVELS * SLOTS
+    overdue: Vec<(K, u64)>,     // scheduled behind `tick`
+    deadlines: HashMap<K, u64>, // the live deadline per key
+}
+
+impl<K: Copy + Eq + Hash> TimerWheel<K> {
+    pub fn new(now_ms: u128) -> Self {
+        Self {
+            tick: now_ms as u64,
+            slots: (0..LEVELS * SLOTS).map(|_| Vec::new()).collect(),
+            overdue: Vec::new(),
+            deadlines: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+
+    // (Re)arms `key`; a deadline already in the past fires on the next expire().
+    pub fn schedule(&mut self, key: K, deadline_ms: u128) {
+        let d = deadline_ms as u64;
+        if self.deadlines.insert(key, d) == Some(d) {
+            return;
+        }
+        if d < self.tick {
+            self.overdue.push((key, d));
+        } else {
+            self.file(key, d);
+        }
+    }
+
+    pub fn cancel(&mut self, key: K) {
+        self.deadlines.remove(&key);
+    }
+
+    pub fn deadline(&self, key: K) -> Option<u128> {
+        self.deadlines.get(&key).map(|&d| d as u128)
+    }
+
+    // Keys whose deadline is <= now_ms; each is disarmed as it is returned.
+    pub fn expire(&mut self, now_ms: u128) -> Vec<K> {
+        let now = now_ms as u64;
+        let mut fired = Vec::new();
+        for (k, d) in std::mem::take(&mut self.overdue) {
+            if self.deadlines.get(&k) == Some(&d) {
+                self.deadlines.remove(&k);
+                fired.push(k);
+            }
+        }
+        if self.deadlines.is_empty() {
+            self.tick = self.tick.max(now + 1);
+            self.slots.iter_mut().for_each(Vec::clear);
+            return fired;
+        }
+        while self.tick <= now {
+            let t = self.tick;
+            if t & MASK == 0 {
+                self.cascade(t);
+            }
+            let due = std::mem::take(&mut self.slots[(t & MASK) as usize]);
+            for (k, d) in due {
+                if self.deadlines.get(&k) != Some(&d) {
+                    continue; // rescheduled or cancelled since
+                }
+                if d <= t {
+                    self.deadlines.remove(&k);
+                    fired.push(k);
+                } else {
+                    self.file(k, d);
+                }
+            }
+            self.tick += 1;
+        }
+        fired
+    }
+
+    // Earliest pending deadline. Exact for the next 64 ms, otherwise a lower
+    // bound: waking at it and calling expire() is always safe.
+    pub fn next_deadline(&self) -> Option<u128> {
+        if self.deadlines.is_empty() {
+            return None;
+        }
+        let mut best: Option<u64> = self
+            .overdue
+            .iter()
+            .filter(|(k, d)| self.deadlines.get(k) == Some(d))
+            .map(|&(_, d)| d)
+            .min();
+        for level in 0..LEVELS {
+            let shift = SLOT_BITS * level as u32;
+            // once a period is under way its slot has been cascaded, so
+            // anything in it now is a full turn away: scan it last
+            let started = self.tick & ((1u64 << shift) - 1) != 0;
+            let first = if started { 1 } else { 0 };
+            for i in first..first + SLOTS as u64 {
+                let idx = ((self.tick >> shift).wrapping_add(i) & MASK) as usize;
+                let live = self.slots[level * SLOTS + idx]
+                    .iter()
+                    .filter(|(k, d)| self.deadlines.get(k) == Some(d))
+                    .map(|&(_, d)| d)
+                    .min();
+                if let Some(d) = live {
+                    best = Some(best.map_or(d, |b| b.min(d)));
+                    // the top level also holds clamped far-future entries
+                    // out of slot order, so it is scanned in full
+                    if level < LEVELS - 1 {
+                        break;
+                    }
+                }
+            }
+        }
+        best.map(|d| d as u128)
+    }
+
+    fn file(&mut self, key: K, deadline: u64) {
+        let delta = deadline.saturating_sub(self.tick).min(SPAN - 1);
+        let mut level = 0;
+        while level < LEVELS - 1 && delta >= 1 << (SLOT_BITS * (level as u32 + 1)) {
+            level += 1;
+        }
+        let at = self.tick + delta;
+        let idx = ((at >> (SLOT_BITS * level as u32)) & MASK) as usize;
+        self.slots[level * SLOTS + idx].push((key, deadline));
+    }
+
+    // At a level-0 wrap, pull down the slots whose period starts now,
+    // highest level first so re-filed entries land in slots still to come.
+    fn cascade(&mut self, t: u64) {
+        let mut top = 0;
+        while top + 1 < LEVELS && (t >> (SLOT_BITS * top as u32)) & MASK == 0 {
+            top += 1;
+        }
+        for level in (1..=top).rev() {
+            let idx = ((t >> (SLOT_BITS * level as u32)) & MASK) as usize;
+            let entries = std::mem::take(&mut self.slots[level * SLOTS + idx]);
+            for (k, d) in entries {
+                if self.deadlines.get(&k) == Some(&d) {
+                    self.file(k, d);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deadlines on both sides of every level boundary, and one past the
+    // wheel's span (parked at the top level and re-filed).
+    fn boundaries(start: u64) -> Vec<u64> {
+        let mut v = vec![1, 2];
+        for level in 1..LEVELS as u32 {
+            let b = 1u64 << (SLOT_BITS * level);
+            v.extend([b - 1, b, b + 1]);
+        }
+        v.push(SPAN + 1000);
+        v.into_iter().map(|d| start + d).collect()
+    }
+
+    #[test]
+    fn fires_on_the_deadline_across_levels() {
+        for start in [0, 1_000_003] {
+            for d in boundaries(start) {
+                let mut w = TimerWheel::new(start as u128);
+                w.schedule(1, d as u128);
+                assert!(w.expire(d as u128 - 1).is_empty(), "{d} fired early");
+                assert_eq!(w.expire(d as u128), vec![1], "{d} did not fire");
+                assert!(w.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn next_deadline_never_overshoots() {
+        let start = 1_000_003;
+        let mut w = TimerWheel::new(start as u128);
+        let mut pending: HashMap<usize, u64> = boundaries(start).into_iter().enumerate().collect();
+        for (&k, &d) in &pending {
+            w.schedule(k, d as u128);
+        }
+        // jumping from deadline to deadline, as a poll loop would
+        while !pending.is_empty() {
+            let earliest = *pending.values().min().unwrap();
+            let next = w.next_deadline().unwrap() as u64;
+            assert!(next <= earliest, "next_deadline {next} after {earliest}");
+            for k in w.expire(next as u128) {
+                assert_eq!(pending.remove(&k), Some(next));
+            }
+        }
+        assert_eq!(w.next_deadline(), None);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut w = TimerWheel::new(0);
+        w.schedule(1, 100);
+        w.schedule(2, 5000);
+        w.cancel(1);
+        assert_eq!(w.len(), 1);
+        assert_eq!(w.deadline(1), None);
+        assert_eq!(w.next_deadline(), Some(5000));
+        assert!(w.expire(4999).is_empty());
+        assert_eq!(w.expire(5000), vec![2]);
+        w.cancel(2); // already fired
+        assert!(w.is_empty());
+    }
+
+    #[test]
+    fn reschedule_replaces_the_deadline() {
+        let mut w = TimerWheel::new(0);
+        w.schedule(1, 50);
+        w.schedule(1, 150);
+        assert_eq!(w.len(), 1);
+        assert_eq!(w.next_deadline(), Some(150));
+        assert!(w.expire(100).is_empty());
+        w.schedule(1, 120);
+        assert_eq!(w.expire(120), vec![1]);
+        assert!(w.expire(200).is_empty());
+    }
+
+    #[test]
+    fn overdue_fires_next() {
+        let mut w = TimerWheel::new(0);
+        assert!(w.expire(1000).is_empty());
+        w.schedule(1, 10);
+        assert_eq!(w.next_deadline(), Some(10));
+        assert_eq!(w.expire(1000), vec![1]);
+    }
+}
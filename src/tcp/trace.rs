@@ -0,0 +1,175 @@
+// Connection tracing: TcpStack reports what each connection does to a
+// TcpObserver, in order. JsonLinesTracer is the built-in observer; it keeps
+// one `.jsonl` file per connection, which src/bin/trace2csv.rs turns into
+// seq/cwnd-over-time CSV, and one more for segments no connection took.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use super::{FourTuple, State};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    NoListener,         // segment for a port nobody listens on
+    AcceptQueueFull,    // handshake ACK while accept() is behind
+    BadSynCookie,       // ACK that matches no connection and no valid cookie
+    Duplicate,          // data we already had
+    ReassemblyFull,     // out-of-order data past the reassembly cap
+    ReceiveBufferFull,  // in-order data app_read had no room for
+    KeepaliveTimeout,   // connection: peer stopped answering probes
+    SynTimeout,         // connection: syn_retries SYNs (synack_retries SYN-ACKs) went unanswered
+    RetransmitTimeout,  // connection: data_retries RTOs without progress
+    TimeWaitOverflow,   // connection: closed early, max_time_wait reached
+    Reset,              // connection: the peer sent RST
+    Unreachable,        // connection: ICMP destination unreachable
+    Aborted,            // connection: reset by the application
+    LingerTimeout,      // connection: FIN not acked within the linger time
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetransmitKind {
+    Syn,
+    Rto,
+    Fast, // third duplicate ACK
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegSummary {
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u16,
+    pub len: usize,
+    pub wnd: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpEvent {
+    State { from: State, to: State },
+    SegIn(SegSummary),
+    SegOut(SegSummary),
+    Retransmit { kind: RetransmitKind, seq: u32, len: usize },
+    Cwnd { cwnd: usize, ssthresh: usize },
+    RttSample { rtt_ms: u64, srtt_ms: u64, rttvar_ms: u64, rto_ms: u64 },
+    Drop { reason: DropReason, len: usize },
+}
+
+pub trait TcpObserver: Send {
+    // `conn` is the connection key (peer -> us), as in TcpStack::conns.
+    fn on_event(&mut self, now_ms: u128, conn: FourTuple, ev: &TcpEvent);
+    // An event for a segment that matched no connection, such as a Drop
+    // for NoListener; `conn` is the tuple it was addressed to.
+    fn on_unmatched(&mut self, now_ms: u128, conn: FourTuple, ev: &TcpEvent) {
+        self.on_event(now_ms, conn, ev);
+    }
+}
+
+fn flag_letters(flags: u16) -> String {
+    [(0x01, 'F'), (0x02, 'S'), (0x04, 'R'), (0x08, 'P'), (0x10, 'A'), (0x20, 'U')]
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|&(_, c)| c)
+        .collect()
+}
+
+impl TcpEvent {
+    // One flat JSON object, e.g. {"t":12,"ev":"cwnd","cwnd":2920,"ssthresh":65536}
+    pub fn to_json(self, now_ms: u128) -> String {
+        let mut s = format!("{{\"t\":{now_ms},");
+        let _ = match self {
+            TcpEvent::State { from, to } => {
+                write!(s, "\"ev\":\"state\",\"from\":\"{from:?}\",\"to\":\"{to:?}\"")
+            }
+            TcpEvent::SegIn(g) | TcpEvent::SegOut(g) => write!(
+                s,
+                "\"ev\":\"{}\",\"seq\":{},\"ack\":{},\"flags\":\"{}\",\"len\":{},\"wnd\":{}",
+                if matches!(self, TcpEvent::SegIn(_)) { "seg_in" } else { "seg_out" },
+                g.seq,
+                g.ack,
+                flag_letters(g.flags),
+                g.len,
+                g.wnd
+            ),
+            TcpEvent::Retransmit { kind, seq, len } => {
+                write!(s, "\"ev\":\"retransmit\",\"kind\":\"{kind:?}\",\"seq\":{seq},\"len\":{len}")
+            }
+            TcpEvent::Cwnd { cwnd, ssthresh } => {
+                write!(s, "\"ev\":\"cwnd\",\"cwnd\":{cwnd},\"ssthresh\":{ssthresh}")
+            }
+            TcpEvent::RttSample { rtt_ms, srtt_ms, rttvar_ms, rto_ms } => write!(
+                s,
+                "\"ev\":\"rtt\",\"rtt_ms\":{rtt_ms},\"srtt_ms\":{srtt_ms},\"rttvar_ms\":{rttvar_ms},\"rto_ms\":{rto_ms}"
+            ),
+            TcpEvent::Drop { reason, len } => {
+                write!(s, "\"ev\":\"drop\",\"reason\":\"{reason:?}\",\"len\":{len}")
+            }
+        };
+        s.push('}');
+        s
+    }
+}
+
+// Writes <dir>/<local ip>_<port>-<peer ip>_<port>.jsonl per connection; a
+// file is closed when its connection reaches Closed. Unmatched segments
+// all go to <dir>/unmatched.jsonl, each line naming its tuple, so a port
+// scan costs one file rather than one per probe.
+pub struct JsonLinesTracer {
+    dir: PathBuf,
+    files: HashMap<FourTuple, BufWriter<File>>,
+    unmatched: Option<File>,
+}
+
+impl JsonLinesTracer {
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, files: HashMap::new(), unmatched: None })
+    }
+
+    fn file_name(k: &FourTuple) -> String {
+        format!("{}_{}-{}_{}.jsonl", ip(k.dst_ip), k.dst_port, ip(k.src_ip), k.src_port)
+    }
+}
+
+fn ip(a: [u8; 4]) -> String {
+    format!("{}.{}.{}.{}", a[0], a[1], a[2], a[3])
+}
+
+impl TcpObserver for JsonLinesTracer {
+    fn on_event(&mut self, now_ms: u128, conn: FourTuple, ev: &TcpEvent) {
+        if !self.files.contains_key(&conn) {
+            let path = self.dir.join(Self::file_name(&conn));
+            let Ok(f) = File::options().create(true).append(true).open(path) else {
+                return; // tracing is best-effort
+            };
+            self.files.insert(conn, BufWriter::new(f));
+        }
+        let w = self.files.get_mut(&conn).unwrap();
+        let _ = writeln!(w, "{}", ev.to_json(now_ms));
+        if let TcpEvent::State { to: State::Closed, .. } = ev {
+            let _ = w.flush();
+            self.files.remove(&conn);
+        }
+    }
+
+    fn on_unmatched(&mut self, now_ms: u128, conn: FourTuple, ev: &TcpEvent) {
+        if self.unmatched.is_none() {
+            let path = self.dir.join("unmatched.jsonl");
+            self.unmatched = File::options().create(true).append(true).open(path).ok();
+        }
+        let Some(f) = &mut self.unmatched else {
+            return;
+        };
+        // unbuffered: nothing closes this file while the stack runs
+        let json = ev.to_json(now_ms);
+        let (local, peer) = (ip(conn.dst_ip), ip(conn.src_ip));
+        let _ = writeln!(
+            f,
+            "{{\"local\":\"{local}:{}\",\"peer\":\"{peer}:{}\",{}",
+            conn.dst_port,
+            conn.src_port,
+            &json[1..]
+        );
+    }
+}
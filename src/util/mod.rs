@@ -1,3 +1,5 @@
 pub mod hexdump;
 pub mod checksum;
 pub mod time;
+pub mod ring;
+pub mod snmp;
@@ -0,0 +1,194 @@
+// Fixed-capacity byte ring with slice copies in and out.
+//
+// Used for the TCP send queue (which keeps sent-but-unacked bytes so
+// retransmissions can peek them by offset) and the in-order receive queue.
+
+#[derive(Clone, Debug)]
+pub struct RingBuf {
+    buf: Vec<u8>,
+    head: usize, // index of the oldest byte
+    len: usize,
+}
+
+impl RingBuf {
+    pub fn new(capacity: usize) -> Self {
+        Self { buf: vec![0; capacity], head: 0, len: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn free(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    // Appends as much of `data` as fits; returns the number of bytes taken.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(self.free());
+        if n == 0 {
+            return 0;
+        }
+        let cap = self.buf.len();
+        let tail = (self.head + self.len) % cap;
+        let first = n.min(cap - tail);
+        self.buf[tail..tail + first].copy_from_slice(&data[..first]);
+        self.buf[..n - first].copy_from_slice(&data[first..n]);
+        self.len += n;
+        n
+    }
+
+    // Copies bytes starting `offset` past the head into `out`, without
+    // consuming them; returns the number copied.
+    pub fn peek_at(&self, offset: usize, out: &mut [u8]) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let n = out.len().min(self.len - offset);
+        let cap = self.buf.len();
+        let start = (self.head + offset) % cap;
+        let first = n.min(cap - start);
+        out[..first].copy_from_slice(&self.buf[start..start + first]);
+        out[first..n].copy_from_slice(&self.buf[..n - first]);
+        n
+    }
+
+    // Drops up to `n` bytes from the head; returns the number dropped.
+    pub fn consume(&mut self, n: usize) -> usize {
+        let n = n.min(self.len);
+        if n > 0 {
+            self.head = (self.head + n) % self.buf.len();
+            self.len -= n;
+        }
+        n
+    }
+
+    // peek_at(0) + consume.
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let n = self.peek_at(0, out);
+        self.consume(n)
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    // Changes capacity, keeping the queued bytes: it never shrinks below
+    // len(), so a live connection loses nothing it has queued or acked.
+    pub fn resize(&mut self, capacity: usize) {
+        let mut buf = vec![0; capacity.max(self.len)];
+        let n = self.peek_at(0, &mut buf);
+        self.buf = buf;
+        self.head = 0;
+        self.len = n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A ring of `cap` whose head sits at `head`, empty.
+    fn at(cap: usize, head: usize) -> RingBuf {
+        let mut r = RingBuf::new(cap);
+        r.write(&vec![0; head]);
+        r.consume(head);
+        r
+    }
+
+    #[test]
+    fn wraps_on_write_and_read() {
+        let mut r = at(8, 6);
+        assert_eq!(r.write(b"abcde"), 5); // 2 at the end, 3 at the start
+        assert_eq!(r.len(), 5);
+        let mut out = [0; 8];
+        assert_eq!(r.read(&mut out), 5);
+        assert_eq!(&out[..5], b"abcde");
+        assert!(r.is_empty());
+        // and once more round
+        assert_eq!(r.write(b"12345678"), 8);
+        assert_eq!(r.read(&mut out), 8);
+        assert_eq!(&out, b"12345678");
+    }
+
+    #[test]
+    fn peek_at_across_the_wrap() {
+        let mut r = at(8, 5);
+        r.write(b"abcdefg");
+        let mut out = [0; 4];
+        assert_eq!(r.peek_at(1, &mut out), 4);
+        assert_eq!(&out, b"bcde");
+        assert_eq!(r.peek_at(5, &mut out), 2);
+        assert_eq!(&out[..2], b"fg");
+        assert_eq!(r.peek_at(7, &mut out), 0);
+        assert_eq!(r.peek_at(100, &mut out), 0);
+        assert_eq!(r.len(), 7); // peeking takes nothing
+    }
+
+    #[test]
+    fn consume() {
+        let mut r = at(8, 6);
+        r.write(b"abcdef");
+        assert_eq!(r.consume(3), 3);
+        let mut out = [0; 8];
+        assert_eq!(r.peek_at(0, &mut out), 3);
+        assert_eq!(&out[..3], b"def");
+        assert_eq!(r.consume(10), 3);
+        assert_eq!(r.consume(1), 0);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn full_and_empty() {
+        let mut r = RingBuf::new(4);
+        let mut out = [0; 4];
+        assert_eq!(r.read(&mut out), 0);
+        assert_eq!(r.write(b"abcdef"), 4);
+        assert_eq!(r.free(), 0);
+        assert_eq!(r.write(b"x"), 0);
+        assert_eq!(r.read(&mut out[..1]), 1);
+        assert_eq!(r.write(b"xy"), 1);
+        assert_eq!(r.read(&mut out), 4);
+        assert_eq!(&out, b"bcdx");
+        assert_eq!(r.free(), 4);
+
+        let mut none = RingBuf::new(0);
+        assert_eq!(none.write(b"a"), 0);
+        assert_eq!(none.read(&mut out), 0);
+    }
+
+    #[test]
+    fn clear() {
+        let mut r = at(8, 3);
+        r.write(b"abc");
+        r.clear();
+        assert!(r.is_empty());
+        assert_eq!(r.free(), 8);
+    }
+
+    #[test]
+    fn resize_keeps_queued_bytes() {
+        let mut r = at(8, 6);
+        r.write(b"abcdef");
+        r.resize(16);
+        assert_eq!((r.capacity(), r.len()), (16, 6));
+        assert_eq!(r.write(b"ghij"), 4);
+        let mut out = [0; 16];
+        assert_eq!(r.peek_at(0, &mut out), 10);
+        assert_eq!(&out[..10], b"abcdefghij");
+        // shrinking stops at what is queued
+        r.resize(4);
+        assert_eq!((r.capacity(), r.len()), (10, 10));
+        r.consume(8);
+        r.resize(4);
+        assert_eq!((r.capacity(), r.len()), (4, 2));
+        assert_eq!(r.read(&mut out), 2);
+        assert_eq!(&out[..2], b"ij");
+    }
+}
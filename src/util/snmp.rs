@@ -0,0 +1,201 @@
+// Per-stack protocol counters, modelled on Linux's /proc/net/snmp.
+//
+// Each NetStack owns one registry and shares it with its TcpStack; the
+// stack counts what its parsers accept or reject and what it sends. An
+// Snmp is a cheap handle, so a clone can be read from elsewhere (a status
+// page, another thread). Read one with snapshot(); its Display impl prints
+// a `netstat -s`-style report.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ctr {
+    EthInIpv4,
+    EthInArp,
+    EthInOther,
+    EthInErrors, // runt frames
+    EthOutIpv4,
+    EthOutArp,
+    EthOutOther,
+
+    ArpInRequests,
+    ArpInReplies,
+    ArpInErrors,
+    ArpOutRequests,
+    ArpOutReplies,
+
+    IpInReceives,
+    IpInHdrErrors,
+    IpInAddrErrors,
+    IpOutRequests,
+
+    IcmpInMsgs,
+    IcmpInErrors,
+    IcmpOutMsgs,
+
+    TcpActiveOpens,
+    TcpPassiveOpens,
+    TcpAttemptFails,
+    TcpEstabResets,
+    TcpInSegs,
+    TcpOutSegs,
+    TcpRetransSegs,
+    TcpInErrs,
+    TcpOutRsts,
+}
+
+const N: usize = Ctr::TcpOutRsts as usize + 1;
+
+struct Counters {
+    counters: [AtomicU64; N],
+    icmp_in: [AtomicU64; 256], // by type
+    icmp_out: [AtomicU64; 256],
+}
+
+#[derive(Clone)]
+pub struct Snmp(Arc<Counters>);
+
+impl Default for Snmp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snmp {
+    pub fn new() -> Self {
+        Self(Arc::new(Counters {
+            counters: [const { AtomicU64::new(0) }; N],
+            icmp_in: [const { AtomicU64::new(0) }; 256],
+            icmp_out: [const { AtomicU64::new(0) }; 256],
+        }))
+    }
+
+    pub fn inc(&self, c: Ctr) {
+        self.0.counters[c as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // ICMP messages are also histogrammed by type.
+    pub fn icmp_in(&self, icmp_type: u8) {
+        self.inc(Ctr::IcmpInMsgs);
+        self.0.icmp_in[icmp_type as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn icmp_out(&self, icmp_type: u8) {
+        self.inc(Ctr::IcmpOutMsgs);
+        self.0.icmp_out[icmp_type as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, c: Ctr) -> u64 {
+        self.0.counters[c as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let load = |a: &AtomicU64| a.load(Ordering::Relaxed);
+        Snapshot {
+            counters: std::array::from_fn(|i| load(&self.0.counters[i])),
+            icmp_in: std::array::from_fn(|i| load(&self.0.icmp_in[i])),
+            icmp_out: std::array::from_fn(|i| load(&self.0.icmp_out[i])),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    counters: [u64; N],
+    pub icmp_in: [u64; 256],
+    pub icmp_out: [u64; 256],
+}
+
+impl Snapshot {
+    pub fn get(&self, c: Ctr) -> u64 {
+        self.counters[c as usize]
+    }
+}
+
+// (section, [(counter, netstat -s wording)])
+const REPORT: &[(&str, &[(Ctr, &str)])] = &[
+    ("Ethernet", &[
+        (Ctr::EthInIpv4, "IPv4 frames received"),
+        (Ctr::EthInArp, "ARP frames received"),
+        (Ctr::EthInOther, "frames with other ethertypes received"),
+        (Ctr::EthInErrors, "runt frames"),
+        (Ctr::EthOutIpv4, "IPv4 frames sent"),
+        (Ctr::EthOutArp, "ARP frames sent"),
+        (Ctr::EthOutOther, "frames with other ethertypes sent"),
+    ]),
+    ("Arp", &[
+        (Ctr::ArpInRequests, "requests received"),
+        (Ctr::ArpInReplies, "replies received"),
+        (Ctr::ArpInErrors, "malformed packets"),
+        (Ctr::ArpOutRequests, "requests sent"),
+        (Ctr::ArpOutReplies, "replies sent"),
+    ]),
+    ("Ip", &[
+        (Ctr::IpInReceives, "total packets received"),
+        (Ctr::IpInHdrErrors, "with invalid headers"),
+        (Ctr::IpInAddrErrors, "with invalid addresses"),
+        (Ctr::IpOutRequests, "requests sent out"),
+    ]),
+    ("Icmp", &[
+        (Ctr::IcmpInMsgs, "ICMP messages received"),
+        (Ctr::IcmpInErrors, "input ICMP message failed"),
+        (Ctr::IcmpOutMsgs, "ICMP messages sent"),
+    ]),
+    ("Tcp", &[
+        (Ctr::TcpActiveOpens, "active connection openings"),
+        (Ctr::TcpPassiveOpens, "passive connection openings"),
+        (Ctr::TcpAttemptFails, "failed connection attempts"),
+        (Ctr::TcpEstabResets, "connection resets received"),
+        (Ctr::TcpInSegs, "segments received"),
+        (Ctr::TcpOutSegs, "segments sent out"),
+        (Ctr::TcpRetransSegs, "segments retransmitted"),
+        (Ctr::TcpInErrs, "bad segments received"),
+        (Ctr::TcpOutRsts, "resets sent"),
+    ]),
+];
+
+fn icmp_type_name(t: usize) -> Option<&'static str> {
+    Some(match t {
+        0 => "echo replies",
+        3 => "destination unreachable",
+        5 => "redirects",
+        8 => "echo requests",
+        11 => "timeout in transit",
+        12 => "wrong parameters",
+        13 => "timestamp requests",
+        14 => "timestamp replies",
+        _ => return None,
+    })
+}
+
+fn histogram(f: &mut fmt::Formatter<'_>, dir: &str, counts: &[u64; 256]) -> fmt::Result {
+    if counts.iter().all(|&n| n == 0) {
+        return Ok(());
+    }
+    writeln!(f, "    ICMP {dir} histogram:")?;
+    for (t, &n) in counts.iter().enumerate().filter(|(_, n)| **n > 0) {
+        match icmp_type_name(t) {
+            Some(name) => writeln!(f, "        {name}: {n}")?,
+            None => writeln!(f, "        type {t}: {n}")?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (section, rows) in REPORT {
+            writeln!(f, "{section}:")?;
+            for &(c, text) in rows.iter() {
+                writeln!(f, "    {} {text}", self.get(c))?;
+                if c == Ctr::IcmpInErrors {
+                    histogram(f, "input", &self.icmp_in)?;
+                } else if c == Ctr::IcmpOutMsgs {
+                    histogram(f, "output", &self.icmp_out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
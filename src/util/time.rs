@@ -11,41 +11,151 @@ impl Timer {
 
 static START: OnceLock<Instant> = OnceLock::new();
 pub fn now_millis() -> u128 {
-    *START.get_or_init(Instant::now);
-    START.get().unwrap().elapsed().as_millis()
+    START.get_or_init(Instant::now).elapsed().as_millis()
 }
 
-/// RFC 6298-ish RTO (simplified)
+pub const RTO_INITIAL_MS: u64 = 1_000;
+pub const RTO_MIN_MS: u64 = 200;
+pub const RTO_MAX_MS: u64 = 60_000;
+pub const CLOCK_GRANULARITY_MS: u64 = 1; // G: now_millis() resolution
+
+/// RFC 6298 RTO in integer fixed point: srtt is kept scaled by 8 and
+/// rttvar by 4, so alpha = 1/8 and beta = 1/4 become shifts.
 #[derive(Clone, Debug)]
 pub struct RtoCalc {
     inited: bool,
-    srtt: f64,
-    rttvar: f64,
+    srtt8: u64,
+    rttvar4: u64,
+    pub backoffs: u32, // consecutive expirations since the last sample
     pub rto_ms: u64,
+    pub min_ms: u64,
+    pub max_ms: u64,
+}
+impl Default for RtoCalc {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl RtoCalc {
     pub fn new() -> Self {
-        Self { inited: false, srtt: 0.0, rttvar: 0.0, rto_ms: 1000 }
+        Self {
+            inited: false,
+            srtt8: 0,
+            rttvar4: 0,
+            backoffs: 0,
+            rto_ms: RTO_INITIAL_MS,
+            min_ms: RTO_MIN_MS,
+            max_ms: RTO_MAX_MS,
+        }
+    }
+    // Before the first sample `initial_ms` is the RTO; after it, only the
+    // clamp changes.
+    pub fn set_bounds(&mut self, initial_ms: u64, min_ms: u64, max_ms: u64) {
+        self.min_ms = min_ms;
+        self.max_ms = max_ms.max(min_ms);
+        self.rto_ms = if self.inited { self.base_rto() } else { initial_ms };
     }
+    // Only feed samples from segments that were never retransmitted (Karn).
     pub fn sample(&mut self, rtt_ms: u64) {
-        let rtt = rtt_ms as f64;
         if !self.inited {
+            // 2.2: SRTT = R, RTTVAR = R/2
             self.inited = true;
-            self.srtt = rtt;
-            self.rttvar = rtt / 2.0;
+            self.srtt8 = rtt_ms << 3;
+            self.rttvar4 = rtt_ms << 1;
         } else {
-            let a = 1.0/8.0;
-            let b = 1.0/4.0;
-            let err = (self.srtt - rtt).abs();
-            self.rttvar = (1.0 - b) * self.rttvar + b * err;
-            self.srtt   = (1.0 - a) * self.srtt   + a * rtt;
+            // 2.3: RTTVAR = 3/4 RTTVAR + 1/4 |SRTT - R'|, SRTT = 7/8 SRTT + 1/8 R'
+            let err = (self.srtt8 >> 3).abs_diff(rtt_ms);
+            self.rttvar4 = self.rttvar4 - (self.rttvar4 >> 2) + err;
+            self.srtt8 = self.srtt8 - (self.srtt8 >> 3) + rtt_ms;
         }
-        let mut rto = self.srtt + 4.0 * self.rttvar;
-        if rto < 200.0 { rto = 200.0; }
-        if rto > 60000.0 { rto = 60000.0; }
-        self.rto_ms = rto as u64;
+        // a fresh sample also collapses any backed-off RTO (5.7)
+        self.backoffs = 0;
+        self.rto_ms = self.base_rto();
     }
+    pub fn srtt_ms(&self) -> Option<u64> {
+        self.inited.then_some(self.srtt8 >> 3)
+    }
+    pub fn rttvar_ms(&self) -> Option<u64> {
+        self.inited.then_some(self.rttvar4 >> 2)
+    }
+    // 5.5: double on every expiration
     pub fn backoff(&mut self) {
-        self.rto_ms = (self.rto_ms * 2).clamp(200, 120_000);
+        self.backoffs += 1;
+        self.rto_ms = (self.rto_ms * 2).clamp(self.min_ms, self.max_ms);
+    }
+    // 2.3: RTO = SRTT + max(G, K*RTTVAR), K = 4
+    fn base_rto(&self) -> u64 {
+        let rto = (self.srtt8 >> 3) + self.rttvar4.max(CLOCK_GRANULARITY_MS);
+        rto.clamp(self.min_ms, self.max_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_sample() {
+        let mut r = RtoCalc::new();
+        assert_eq!((r.srtt_ms(), r.rttvar_ms(), r.rto_ms), (None, None, RTO_INITIAL_MS));
+        r.sample(100);
+        // SRTT = R, RTTVAR = R/2, RTO = SRTT + 4 * RTTVAR
+        assert_eq!((r.srtt_ms(), r.rttvar_ms(), r.rto_ms), (Some(100), Some(50), 300));
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut r = RtoCalc::new();
+        r.sample(100);
+        r.sample(200);
+        // RTTVAR = 3/4 * 50 + 1/4 * 100, SRTT = 7/8 * 100 + 1/8 * 200
+        assert_eq!((r.srtt_ms(), r.rttvar_ms(), r.rto_ms), (Some(112), Some(62), 362));
+        // a steady RTT settles the variance
+        for _ in 0..50 {
+            r.sample(200);
+        }
+        assert_eq!(r.srtt_ms(), Some(200));
+        assert!(r.rto_ms < 250, "rto {}", r.rto_ms);
+    }
+
+    #[test]
+    fn clamps_to_bounds() {
+        let mut r = RtoCalc::new();
+        r.sample(1);
+        assert_eq!(r.rto_ms, RTO_MIN_MS);
+        let mut r = RtoCalc::new();
+        r.sample(50_000);
+        assert_eq!(r.rto_ms, RTO_MAX_MS);
+        // without a floor, the clock granularity is the least variance term
+        let mut r = RtoCalc::new();
+        r.set_bounds(1000, 0, 1000);
+        r.sample(0);
+        assert_eq!(r.rto_ms, CLOCK_GRANULARITY_MS);
+    }
+
+    #[test]
+    fn set_bounds() {
+        let mut r = RtoCalc::new();
+        r.set_bounds(3000, 100, 10_000);
+        assert_eq!(r.rto_ms, 3000); // no sample yet: the initial RTO
+        r.sample(10);
+        assert_eq!(r.rto_ms, 100);
+        r.set_bounds(3000, 500, 10_000);
+        assert_eq!(r.rto_ms, 500); // re-clamped, not reset to the initial RTO
+        r.set_bounds(3000, 500, 100);
+        assert_eq!(r.max_ms, 500);
+    }
+
+    #[test]
+    fn backoff_doubles_and_a_sample_resets_it() {
+        let mut r = RtoCalc::new();
+        for (n, want) in [2000, 4000, 8000, 16_000, 32_000, 60_000, 60_000].into_iter().enumerate() {
+            r.backoff();
+            assert_eq!(r.rto_ms, want);
+            assert_eq!(r.backoffs, n as u32 + 1);
+        }
+        r.sample(100);
+        assert_eq!((r.backoffs, r.rto_ms), (0, 300));
     }
 }